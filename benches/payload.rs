@@ -0,0 +1,103 @@
+//! Compares the size and the serialization cost of a verbose record with and
+//! without compression. The sizes are printed before the measurements.
+//!
+//! Run with `cargo bench --bench payload --features zstd` to include zstd.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use state_tracker::compression::{CompressionAlgorithm, CompressionConfig};
+use state_tracker::encoding::{self, PayloadFormat};
+use state_tracker::error_info::ErrorInfo;
+use state_tracker::state::State;
+use state_tracker::tracked_data::TrackedData;
+use std::time::SystemTime;
+
+fn verbose_error() -> TrackedData {
+    let mut tracked_data = TrackedData::new(
+        "orders_consumer".to_string(),
+        State::Error("failed to process order: connection reset by peer".to_string()),
+        SystemTime::now(),
+    );
+
+    let mut error_info = ErrorInfo::new("io::ConnectionReset", "connection reset by peer");
+    error_info.sources = (0..8)
+        .map(|depth| {
+            format!(
+                "failed to process order at depth {}: connection reset",
+                depth
+            )
+        })
+        .collect();
+    error_info.backtrace = Some(
+        (0..32)
+            .map(|frame| format!("{}: orders_consumer::process::h{:016x}\n", frame, frame))
+            .collect(),
+    );
+    tracked_data.error_info = Some(error_info);
+
+    for label in 0..16 {
+        tracked_data
+            .labels
+            .insert(format!("label_{}", label), format!("value_{}", label));
+    }
+
+    tracked_data
+}
+
+fn payload_formats() -> Vec<(&'static str, PayloadFormat)> {
+    let compressed = |algorithm| PayloadFormat {
+        compression: Some(CompressionConfig {
+            algorithm,
+            threshold_in_bytes: 0,
+        }),
+        ..PayloadFormat::default()
+    };
+
+    vec![
+        ("deflate", compressed(CompressionAlgorithm::Deflate)),
+        #[cfg(feature = "zstd")]
+        ("zstd", compressed(CompressionAlgorithm::Zstd)),
+    ]
+}
+
+fn serialization(criterion: &mut Criterion) {
+    let tracked_data = verbose_error();
+
+    println!(
+        "serde_json: {} bytes",
+        serde_json::to_vec(&tracked_data).unwrap().len()
+    );
+    for (name, payload_format) in payload_formats() {
+        println!(
+            "{}: {} bytes",
+            name,
+            payload_format.serialize(&tracked_data).unwrap().len()
+        );
+    }
+
+    let mut group = criterion.benchmark_group("serialize");
+    group.bench_function("serde_json", |bencher| {
+        bencher.iter(|| serde_json::to_vec(black_box(&tracked_data)).unwrap())
+    });
+    for (name, payload_format) in payload_formats() {
+        group.bench_function(name, |bencher| {
+            bencher.iter(|| payload_format.serialize(black_box(&tracked_data)).unwrap())
+        });
+    }
+    group.finish();
+
+    let mut group = criterion.benchmark_group("decode");
+    let payload = serde_json::to_vec(&tracked_data).unwrap();
+    group.bench_function("serde_json", |bencher| {
+        bencher.iter(|| encoding::decode(black_box(&payload)).unwrap())
+    });
+    for (name, payload_format) in payload_formats() {
+        let payload = payload_format.serialize(&tracked_data).unwrap();
+        group.bench_function(name, |bencher| {
+            bencher.iter(|| encoding::decode(black_box(&payload)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, serialization);
+criterion_main!(benches);
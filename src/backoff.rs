@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+/// Exponentially growing delay between attempts of an operation which keeps failing.
+pub(crate) struct Backoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    next_delay: Duration,
+    jitter: bool,
+}
+
+impl Backoff {
+    pub(crate) fn new(initial_delay: Duration, max_delay: Duration) -> Backoff {
+        Backoff {
+            initial_delay,
+            max_delay,
+            next_delay: initial_delay,
+            jitter: false,
+        }
+    }
+
+    /// Randomizes every delay to between half and all of it, so that
+    /// simultaneous failures do not lead to simultaneous attempts.
+    pub(crate) fn with_jitter(mut self) -> Backoff {
+        self.jitter = true;
+        self
+    }
+
+    /// Delay to wait before the next attempt, doubling the one after it.
+    pub(crate) fn next_delay(&mut self) -> Duration {
+        let delay = self.next_delay;
+        self.next_delay = (self.next_delay * 2).min(self.max_delay);
+
+        if self.jitter {
+            delay.mul_f64(0.5 + fastrand::f64() / 2.0)
+        } else {
+            delay
+        }
+    }
+
+    /// Starts over from the initial delay, after an attempt succeeded.
+    pub(crate) fn reset(&mut self) {
+        self.next_delay = self.initial_delay;
+    }
+}
@@ -0,0 +1,141 @@
+//! Serves the aggregated states received from StateTrackers over HTTP.
+//!
+//! Usage: `state-tracker-http <config path> <listen address> [required id...]`
+//!
+//! * `/healthz` - 200 unless any component is in an error state.
+//! * `/readyz` - 200 only when every required id is in the valid state, and never
+//!   before a first state has been received.
+//! * `/states` - JSON dump of the latest state of every component.
+//!
+//! The configuration is a [`StateCollectorConfig`], whose keys are named as those of the
+//! trackers' configuration. When it has a `state_signing` key, unsigned or tampered states
+//! are rejected, as are the states of processes whose uid is missing from `state_allowed_uids`.
+//!
+//! The process exits if receiving states fails, since its socket is then unusable.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use state_tracker::state_collector::StateCollector;
+use state_tracker::state_collector_config::StateCollectorConfig;
+use state_tracker::state_registry::{ComponentState, Health, StateRegistry};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+#[derive(Clone)]
+struct HttpState {
+    state_registry: Arc<RwLock<StateRegistry>>,
+    required_ids: Arc<Vec<String>>,
+}
+
+impl HttpState {
+    fn state_registry(&self) -> RwLockReadGuard<'_, StateRegistry> {
+        self.state_registry
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    simple_logger::init_with_level(log::Level::Info).expect("failed to initialize logger");
+
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    if arguments.len() < 2 {
+        eprintln!("usage: state-tracker-http <config path> <listen address> [required id...]");
+        std::process::exit(2);
+    }
+
+    let state_collector_config = match read_config(&arguments[0]) {
+        Ok(state_collector_config) => state_collector_config,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let mut state_collector = match StateCollector::try_from_config(&state_collector_config) {
+        Ok(state_collector) => state_collector,
+        Err(error) => {
+            eprintln!("failed to initialize state collector: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let http_state = HttpState {
+        state_registry: Arc::new(RwLock::new(StateRegistry::new())),
+        required_ids: Arc::new(arguments[2..].to_vec()),
+    };
+
+    let state_registry = http_state.state_registry.clone();
+    tokio::spawn(async move {
+        loop {
+            match state_collector.receive().await {
+                Ok(tracked_data) => state_registry
+                    .write()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .update(tracked_data),
+                Err(error) => {
+                    // The socket is unusable, so exiting lets the process be restarted
+                    // rather than serving outdated states.
+                    log::error!("stopped collecting states: {}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+    });
+
+    let router = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/states", get(states))
+        .with_state(http_state);
+
+    let listener = match tokio::net::TcpListener::bind(&arguments[1]).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            eprintln!("failed to listen on '{}': {}", arguments[1], error);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(error) = axum::serve(listener, router).await {
+        eprintln!("failed to serve http requests: {}", error);
+        std::process::exit(1);
+    }
+}
+
+fn read_config(path: &str) -> Result<StateCollectorConfig, String> {
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(error) => return Err(format!("failed to read config '{}': {}", path, error)),
+    };
+
+    match serde_json::from_slice(&content) {
+        Ok(state_collector_config) => Ok(state_collector_config),
+        Err(error) => Err(format!("failed to parse config '{}': {}", path, error)),
+    }
+}
+
+async fn healthz(State(http_state): State<HttpState>) -> StatusCode {
+    match http_state.state_registry().health() {
+        Health::Healthy | Health::Degraded => StatusCode::OK,
+        Health::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+async fn readyz(State(http_state): State<HttpState>) -> StatusCode {
+    if http_state
+        .state_registry()
+        .is_ready(&http_state.required_ids)
+    {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+async fn states(State(http_state): State<HttpState>) -> Json<BTreeMap<String, ComponentState>> {
+    Json(http_state.state_registry().components().clone())
+}
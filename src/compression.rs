@@ -0,0 +1,161 @@
+//! Compression of the encoded TrackedData, which is preceded by a byte tagging
+//! the algorithm so that [`crate::encoding::decode`] can decompress it.
+
+use crate::error::{Error, ErrorKind};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+pub(crate) const DEFLATE_TAG: u8 = 0x10;
+#[cfg(feature = "zstd")]
+pub(crate) const ZSTD_TAG: u8 = 0x11;
+
+/// Largest size to which a payload gets decompressed, guarding receivers
+/// against payloads crafted to expand indefinitely.
+const MAX_DECOMPRESSED_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionAlgorithm {
+    #[default]
+    Deflate,
+    /// Zstandard, requiring the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CompressionConfig {
+    #[serde(default)]
+    pub algorithm: CompressionAlgorithm,
+    /// Size of the encoded data from which it gets compressed, since compressing
+    /// small payloads costs more than it saves.
+    #[serde(default = "default_threshold_in_bytes")]
+    pub threshold_in_bytes: usize,
+}
+
+fn default_threshold_in_bytes() -> usize {
+    512
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            algorithm: CompressionAlgorithm::default(),
+            threshold_in_bytes: default_threshold_in_bytes(),
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Compresses the payload if it reaches the threshold and compressing it
+    /// makes it smaller, returning `None` if it is left as is.
+    pub(crate) fn apply(&self, payload: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        if payload.len() < self.threshold_in_bytes {
+            return Ok(None);
+        }
+
+        let compressed = compress(self.algorithm, payload)?;
+        if compressed.len() < payload.len() {
+            Ok(Some(compressed))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Compresses the payload, preceding it by the algorithm's tag.
+pub(crate) fn compress(algorithm: CompressionAlgorithm, payload: &[u8]) -> Result<Vec<u8>, Error> {
+    let compressed = match algorithm {
+        CompressionAlgorithm::Deflate => {
+            let mut encoder =
+                DeflateEncoder::new(vec![DEFLATE_TAG], flate2::Compression::default());
+            encoder.write_all(payload).and_then(|_| encoder.finish())
+        }
+        #[cfg(feature = "zstd")]
+        CompressionAlgorithm::Zstd => {
+            let mut compressed = vec![ZSTD_TAG];
+            zstd::stream::copy_encode(payload, &mut compressed, zstd::DEFAULT_COMPRESSION_LEVEL)
+                .map(|_| compressed)
+        }
+    };
+
+    compressed.map_err(|error| {
+        Error::new(
+            ErrorKind::InternalFailure,
+            format!("failed to compress payload: {}", error),
+        )
+    })
+}
+
+/// Decompresses a payload produced by [`compress`], returning `None` if it is
+/// not tagged as compressed by any of the enabled algorithms.
+pub(crate) fn decompress(payload: &[u8]) -> Option<Result<Vec<u8>, Error>> {
+    let decompressed = match payload.first() {
+        Some(&DEFLATE_TAG) => read_bounded(DeflateDecoder::new(&payload[1..])),
+        #[cfg(feature = "zstd")]
+        Some(&ZSTD_TAG) => zstd::stream::read::Decoder::new(&payload[1..]).and_then(read_bounded),
+        _ => return None,
+    };
+
+    Some(decompressed.map_err(|error| {
+        Error::new(
+            ErrorKind::InternalFailure,
+            format!("failed to decompress payload: {}", error),
+        )
+    }))
+}
+
+fn read_bounded(decoder: impl Read) -> std::io::Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    decoder
+        .take(MAX_DECOMPRESSED_SIZE + 1)
+        .read_to_end(&mut payload)?;
+
+    if payload.len() as u64 > MAX_DECOMPRESSED_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "decompressed payload is too large",
+        ));
+    }
+
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compresses_only_large_payloads() {
+        let compression_config = CompressionConfig {
+            threshold_in_bytes: 64,
+            ..CompressionConfig::default()
+        };
+
+        let small_payload = b"{\"id\":\"small\"}".to_vec();
+        assert_eq!(compression_config.apply(&small_payload).unwrap(), None);
+
+        let algorithms = [
+            CompressionAlgorithm::Deflate,
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd,
+        ];
+
+        let large_payload = "{\"id\":\"large\"}".repeat(100).into_bytes();
+        for algorithm in algorithms {
+            let compressed = CompressionConfig {
+                algorithm,
+                ..compression_config
+            }
+            .apply(&large_payload)
+            .unwrap()
+            .unwrap();
+
+            assert!(compressed.len() < large_payload.len());
+            assert_eq!(decompress(&compressed).unwrap().unwrap(), large_payload);
+        }
+    }
+}
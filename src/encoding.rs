@@ -0,0 +1,205 @@
+//! Encodings of the TrackedData sent by the sinks.
+//!
+//! JSON payloads are sent as they are, for compatibility with receivers which
+//! predate the other encodings, while the rest are preceded by a byte tagging
+//! their format, so that [`decode`] can tell them apart. Compressed payloads,
+//! see [`crate::compression`], are tagged the same way.
+
+use crate::compression;
+use crate::compression::CompressionConfig;
+use crate::error::{Error, ErrorKind};
+use crate::signing;
+use crate::signing::SigningKey;
+use crate::timestamp::TimestampFormat;
+use crate::tracked_data::TrackedData;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "msgpack")]
+const MSGPACK_TAG: u8 = 0x01;
+#[cfg(feature = "cbor")]
+const CBOR_TAG: u8 = 0x02;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    /// MessagePack, requiring the `msgpack` feature.
+    #[cfg(feature = "msgpack")]
+    Msgpack,
+    /// CBOR, requiring the `cbor` feature.
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl Encoding {
+    /// Encodes the data, serializing its timestamps in the `timestamp_format`.
+    pub fn encode(
+        &self,
+        tracked_data: &TrackedData,
+        timestamp_format: TimestampFormat,
+    ) -> Result<Vec<u8>, Error> {
+        let tracked_data = tracked_data.with_timestamp_format(timestamp_format);
+
+        match self {
+            Encoding::Json => serde_json::to_vec(&tracked_data).map_err(encoding_error),
+            #[cfg(feature = "msgpack")]
+            Encoding::Msgpack => {
+                let mut payload = vec![MSGPACK_TAG];
+                match rmp_serde::encode::write_named(&mut payload, &tracked_data) {
+                    Ok(_) => Ok(payload),
+                    Err(error) => Err(encoding_error(error)),
+                }
+            }
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => {
+                let mut payload = vec![CBOR_TAG];
+                match ciborium::into_writer(&tracked_data, &mut payload) {
+                    Ok(_) => Ok(payload),
+                    Err(error) => Err(encoding_error(error)),
+                }
+            }
+        }
+    }
+}
+
+/// How the sinks serialize the TrackedData they output.
+#[derive(Clone, Default, Debug)]
+pub struct PayloadFormat {
+    pub encoding: Encoding,
+    pub timestamp_format: TimestampFormat,
+    /// Compression of the large payloads, which are never compressed if missing.
+    pub compression: Option<CompressionConfig>,
+    /// Key signing the payloads, which are unsigned if missing.
+    pub signing_key: Option<SigningKey>,
+}
+
+impl PayloadFormat {
+    pub fn serialize(&self, tracked_data: &TrackedData) -> Result<Vec<u8>, Error> {
+        let payload = self.encode(tracked_data)?;
+
+        match self.compress(&payload)? {
+            Some(compressed) => Ok(self.sign(&compressed)),
+            None => Ok(self.sign(&payload)),
+        }
+    }
+
+    /// Encodes the data, neither compressing nor signing it.
+    pub(crate) fn encode(&self, tracked_data: &TrackedData) -> Result<Vec<u8>, Error> {
+        self.encoding.encode(tracked_data, self.timestamp_format)
+    }
+
+    /// Compresses the encoded payload as configured, returning `None` if it is left as is.
+    pub(crate) fn compress(&self, payload: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        match &self.compression {
+            Some(compression_config) => compression_config.apply(payload),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn sign(&self, payload: &[u8]) -> Vec<u8> {
+        match &self.signing_key {
+            Some(signing_key) => signing_key.sign(payload),
+            None => payload.to_vec(),
+        }
+    }
+
+    /// Amount of bytes which signing adds to the payloads.
+    pub(crate) fn signature_size(&self) -> usize {
+        match &self.signing_key {
+            Some(_) => signing::SIGNED_HEADER_SIZE,
+            None => 0,
+        }
+    }
+}
+
+fn encoding_error(error: impl std::fmt::Display) -> Error {
+    Error::new(
+        ErrorKind::InternalFailure,
+        format!("failed to serialize tracked data: {}", error),
+    )
+}
+
+/// Decodes a payload produced by [`PayloadFormat::serialize`] with any of the enabled
+/// encodings and compression algorithms.
+///
+/// The signature of signed payloads is ignored, see [`SigningKey::verify`] to check it.
+pub fn decode(payload: &[u8]) -> Result<TrackedData, Error> {
+    let payload = signing::strip_signature(payload);
+
+    match compression::decompress(payload) {
+        Some(decompressed) => decode_uncompressed(&decompressed?),
+        None => decode_uncompressed(payload),
+    }
+}
+
+fn decode_uncompressed(payload: &[u8]) -> Result<TrackedData, Error> {
+    let decoded = match payload.first() {
+        #[cfg(feature = "msgpack")]
+        Some(&MSGPACK_TAG) => {
+            rmp_serde::from_slice(&payload[1..]).map_err(|error| error.to_string())
+        }
+        #[cfg(feature = "cbor")]
+        Some(&CBOR_TAG) => ciborium::from_reader(&payload[1..]).map_err(|error| error.to_string()),
+        _ => serde_json::from_slice(payload).map_err(|error| error.to_string()),
+    };
+
+    decoded.map_err(|error| {
+        Error::new(
+            ErrorKind::InternalFailure,
+            format!("failed to deserialize tracked data: {}", error),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::{Envelope, SCHEMA_VERSION};
+    use crate::error_info::ErrorInfo;
+    use crate::state::State;
+    use std::time::SystemTime;
+
+    #[test]
+    fn decodes_every_encoding() {
+        let mut tracked_data = TrackedData::new(
+            "test_id".to_string(),
+            State::Error("error".to_string()),
+            SystemTime::now(),
+        );
+        tracked_data.error_info = Some(ErrorInfo::new("code", "message"));
+        tracked_data
+            .labels
+            .insert("host".to_string(), "localhost".to_string());
+        tracked_data.envelope = Some(Envelope {
+            schema_version: SCHEMA_VERSION,
+            instance_id: uuid::Uuid::new_v4(),
+            booted_at: SystemTime::now(),
+            sequence: 1,
+        });
+
+        let encodings = [
+            Encoding::Json,
+            #[cfg(feature = "msgpack")]
+            Encoding::Msgpack,
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor,
+        ];
+
+        // Both formats are lossless, unlike Unix milliseconds.
+        for timestamp_format in [TimestampFormat::Legacy, TimestampFormat::Rfc3339] {
+            for encoding in encodings {
+                let decoded =
+                    decode(&encoding.encode(&tracked_data, timestamp_format).unwrap()).unwrap();
+
+                assert_eq!(decoded.id, tracked_data.id);
+                assert_eq!(decoded.state, tracked_data.state);
+                assert_eq!(decoded.timestamp, tracked_data.timestamp);
+                assert_eq!(decoded.error_info, tracked_data.error_info);
+                assert_eq!(decoded.labels, tracked_data.labels);
+                assert_eq!(decoded.envelope, tracked_data.envelope);
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use crate::timestamp::{FormattedTimestamp, TimestampFormat};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// Version of the TrackedData schema, increased whenever it changes in a way
+/// which receivers need to know about.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Describes the origin of an outputted TrackedData, so that receivers can detect
+/// lost, duplicated or reordered data as well as tracker restarts.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct Envelope {
+    pub schema_version: u32,
+    /// Id which is randomly generated for every StateTracker instance.
+    pub instance_id: Uuid,
+    /// Time at which the StateTracker instance was created.
+    #[serde(deserialize_with = "crate::timestamp::deserialize")]
+    pub booted_at: SystemTime,
+    /// Number of the data among every output of the StateTracker instance, starting at 0.
+    pub sequence: u64,
+}
+
+impl Envelope {
+    /// Wraps the envelope so that its timestamp gets serialized in the `timestamp_format`.
+    pub(crate) fn with_timestamp_format(
+        &self,
+        timestamp_format: TimestampFormat,
+    ) -> FormattedEnvelope<'_> {
+        FormattedEnvelope {
+            schema_version: self.schema_version,
+            instance_id: &self.instance_id,
+            booted_at: FormattedTimestamp {
+                timestamp: self.booted_at,
+                format: timestamp_format,
+            },
+            sequence: self.sequence,
+        }
+    }
+}
+
+/// Serialized like the [`Envelope`] it mirrors, field by field.
+#[derive(Serialize)]
+pub(crate) struct FormattedEnvelope<'a> {
+    schema_version: u32,
+    instance_id: &'a Uuid,
+    booted_at: FormattedTimestamp,
+    sequence: u64,
+}
+
+/// Amount of tracker instances whose sequences are tracked by default, beyond which
+/// the least recently seen instance is forgotten.
+pub const DEFAULT_TRACKED_INSTANCES: usize = 1024;
+
+/// Amount of sequences preceding the latest one of an instance which are remembered,
+/// so that late data can be told apart from duplicated data.
+const SEQUENCE_WINDOW: u64 = 64;
+
+/// How a received Envelope follows the previous ones.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Continuity {
+    /// First data received from the instance, e.g. after a tracker restarted.
+    NewInstance,
+    /// Data following the previous one received from the instance.
+    Consecutive,
+    /// Data received after `missed` data of the same instance were lost.
+    Gap { missed: u64 },
+    /// Data which was counted as missed in a gap, arriving after newer data.
+    Reordered,
+    /// Data which was already received, or which is too old to tell.
+    Duplicate,
+}
+
+struct InstanceSequences {
+    latest_sequence: u64,
+    /// Bit `n` is set if the sequence `latest_sequence - n` was received.
+    received: u64,
+    /// Value of [`SequenceTracker::tracks`] when the instance was last seen.
+    last_seen: u64,
+}
+
+/// Tracks the latest sequences received from the most recently seen StateTracker instances.
+pub struct SequenceTracker {
+    instances: HashMap<Uuid, InstanceSequences>,
+    capacity: usize,
+    tracks: u64,
+}
+
+impl Default for SequenceTracker {
+    fn default() -> Self {
+        SequenceTracker::with_capacity(DEFAULT_TRACKED_INSTANCES)
+    }
+}
+
+impl SequenceTracker {
+    pub fn new() -> SequenceTracker {
+        SequenceTracker::default()
+    }
+
+    /// Creates an instance tracking the sequences of up to `capacity` tracker instances.
+    pub fn with_capacity(capacity: usize) -> SequenceTracker {
+        SequenceTracker {
+            instances: HashMap::new(),
+            capacity: capacity.max(1),
+            tracks: 0,
+        }
+    }
+
+    /// Registers the Envelope, telling how it follows the previous ones of the same instance.
+    pub fn track(&mut self, envelope: &Envelope) -> Continuity {
+        self.tracks += 1;
+
+        let instance = match self.instances.get_mut(&envelope.instance_id) {
+            Some(instance) => instance,
+            None => {
+                if self.instances.len() >= self.capacity {
+                    self.forget_least_recently_seen();
+                }
+
+                self.instances.insert(
+                    envelope.instance_id,
+                    InstanceSequences {
+                        latest_sequence: envelope.sequence,
+                        received: 1,
+                        last_seen: self.tracks,
+                    },
+                );
+
+                return Continuity::NewInstance;
+            }
+        };
+        instance.last_seen = self.tracks;
+
+        if envelope.sequence > instance.latest_sequence {
+            let shift = envelope.sequence - instance.latest_sequence;
+            let missed = shift - 1;
+
+            instance.received = if shift < SEQUENCE_WINDOW {
+                (instance.received << shift) | 1
+            } else {
+                1
+            };
+            instance.latest_sequence = envelope.sequence;
+
+            return if missed == 0 {
+                Continuity::Consecutive
+            } else {
+                Continuity::Gap { missed }
+            };
+        }
+
+        let offset = instance.latest_sequence - envelope.sequence;
+        if offset >= SEQUENCE_WINDOW || instance.received & (1 << offset) != 0 {
+            return Continuity::Duplicate;
+        }
+
+        instance.received |= 1 << offset;
+        Continuity::Reordered
+    }
+
+    fn forget_least_recently_seen(&mut self) {
+        let least_recently_seen = self
+            .instances
+            .iter()
+            .min_by_key(|(_, instance)| instance.last_seen)
+            .map(|(instance_id, _)| *instance_id);
+
+        if let Some(instance_id) = least_recently_seen {
+            self.instances.remove(&instance_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_gaps_duplicates_and_restarts() {
+        let instance_id = Uuid::new_v4();
+        let envelope = |instance_id: Uuid, sequence: u64| Envelope {
+            schema_version: SCHEMA_VERSION,
+            instance_id,
+            booted_at: SystemTime::now(),
+            sequence,
+        };
+
+        let mut sequence_tracker = SequenceTracker::new();
+
+        assert_eq!(
+            sequence_tracker.track(&envelope(instance_id, 0)),
+            Continuity::NewInstance
+        );
+        assert_eq!(
+            sequence_tracker.track(&envelope(instance_id, 1)),
+            Continuity::Consecutive
+        );
+        assert_eq!(
+            sequence_tracker.track(&envelope(instance_id, 4)),
+            Continuity::Gap { missed: 2 }
+        );
+        assert_eq!(
+            sequence_tracker.track(&envelope(instance_id, 4)),
+            Continuity::Duplicate
+        );
+        assert_eq!(
+            sequence_tracker.track(&envelope(instance_id, 3)),
+            Continuity::Reordered
+        );
+        assert_eq!(
+            sequence_tracker.track(&envelope(instance_id, 3)),
+            Continuity::Duplicate
+        );
+        assert_eq!(
+            sequence_tracker.track(&envelope(instance_id, 1)),
+            Continuity::Duplicate
+        );
+        assert_eq!(
+            sequence_tracker.track(&envelope(Uuid::new_v4(), 0)),
+            Continuity::NewInstance
+        );
+    }
+
+    #[test]
+    fn forgets_least_recently_seen_instances() {
+        let instance_ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let envelope = |instance_id: Uuid, sequence: u64| Envelope {
+            schema_version: SCHEMA_VERSION,
+            instance_id,
+            booted_at: SystemTime::now(),
+            sequence,
+        };
+
+        let mut sequence_tracker = SequenceTracker::with_capacity(2);
+
+        sequence_tracker.track(&envelope(instance_ids[0], 0));
+        sequence_tracker.track(&envelope(instance_ids[1], 0));
+        sequence_tracker.track(&envelope(instance_ids[0], 1));
+        sequence_tracker.track(&envelope(instance_ids[2], 0));
+
+        assert_eq!(sequence_tracker.instances.len(), 2);
+        assert_eq!(
+            sequence_tracker.track(&envelope(instance_ids[0], 2)),
+            Continuity::Consecutive
+        );
+        assert_eq!(
+            sequence_tracker.track(&envelope(instance_ids[1], 1)),
+            Continuity::NewInstance
+        );
+    }
+}
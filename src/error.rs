@@ -3,6 +3,9 @@ use std::fmt;
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ErrorKind {
     InternalFailure,
+    SocketPathInUse,
+    PermissionDenied,
+    ParentDirectoryMissing,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -29,3 +32,17 @@ impl fmt::Display for Error {
         write!(f, "{}", self.message)
     }
 }
+
+impl std::error::Error for Error {}
+
+/// Builds the error returned when a socket cannot be bound to `path`.
+pub(crate) fn bind_error(path: &str, error: std::io::Error) -> Error {
+    let kind = match error.kind() {
+        std::io::ErrorKind::AddrInUse => ErrorKind::SocketPathInUse,
+        std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+        std::io::ErrorKind::NotFound => ErrorKind::ParentDirectoryMissing,
+        _ => ErrorKind::InternalFailure,
+    };
+
+    Error::new(kind, format!("failed to bind to '{}': {}", path, error))
+}
@@ -0,0 +1,153 @@
+use crate::error;
+
+use serde::{Deserialize, Serialize};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::time::Duration;
+
+/// Code of the errors whose origin cannot be identified.
+pub const UNSPECIFIED_ERROR_CODE: &str = "unspecified";
+
+/// Details about the error which led a component into the `State::Error` state.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct ErrorInfo {
+    /// Stable machine-readable identifier of the error.
+    pub code: String,
+    pub message: String,
+    /// Messages of the errors which caused it, from the closest one to the root cause.
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub backtrace: Option<String>,
+    /// Whether the error is expected to go away by itself.
+    #[serde(default)]
+    pub transient: bool,
+    #[serde(default)]
+    pub retry_after_in_seconds: Option<u64>,
+    /// Number of consecutive times the component has reported an error with the same code.
+    #[serde(default = "default_occurrences")]
+    pub occurrences: u64,
+}
+
+fn default_occurrences() -> u64 {
+    1
+}
+
+impl ErrorInfo {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> ErrorInfo {
+        ErrorInfo {
+            code: code.into(),
+            message: message.into(),
+            sources: Vec::new(),
+            backtrace: None,
+            transient: false,
+            retry_after_in_seconds: None,
+            occurrences: default_occurrences(),
+        }
+    }
+
+    /// Builds the details of any error, including its source chain and, if enabled
+    /// through `RUST_BACKTRACE`, the backtrace of the caller.
+    ///
+    /// The code and the transient hint are derived from the first error of the chain
+    /// which is either an `std::io::Error` or an `error::Error`.
+    pub fn from_error(error: &(dyn std::error::Error + 'static)) -> ErrorInfo {
+        let mut error_info = ErrorInfo::new(UNSPECIFIED_ERROR_CODE, error.to_string());
+
+        let mut source = error.source();
+        while let Some(error) = source {
+            error_info.sources.push(error.to_string());
+            source = error.source();
+        }
+
+        let backtrace = Backtrace::capture();
+        if backtrace.status() == BacktraceStatus::Captured {
+            error_info.backtrace = Some(backtrace.to_string());
+        }
+
+        let mut cause = Some(error);
+        while let Some(error) = cause {
+            if let Some(io_error) = error.downcast_ref::<std::io::Error>() {
+                error_info.code = format!("io::{:?}", io_error.kind());
+                error_info.transient = matches!(
+                    io_error.kind(),
+                    std::io::ErrorKind::Interrupted
+                        | std::io::ErrorKind::TimedOut
+                        | std::io::ErrorKind::WouldBlock
+                        | std::io::ErrorKind::ConnectionRefused
+                        | std::io::ErrorKind::ConnectionReset
+                        | std::io::ErrorKind::ConnectionAborted
+                );
+                break;
+            }
+
+            if let Some(error) = error.downcast_ref::<error::Error>() {
+                error_info.code = format!("state_tracker::{:?}", error.kind());
+                break;
+            }
+
+            cause = error.source();
+        }
+
+        error_info
+    }
+
+    pub fn with_transient(mut self, transient: bool) -> ErrorInfo {
+        self.transient = transient;
+        self
+    }
+
+    /// Hints that the operation which failed may be retried after `retry_after`,
+    /// which implies that the error is transient.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> ErrorInfo {
+        self.transient = true;
+        self.retry_after_in_seconds = Some(retry_after.as_secs());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[derive(Debug)]
+    struct WrappingError(std::io::Error);
+
+    impl std::fmt::Display for WrappingError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "failed to connect")
+        }
+    }
+
+    impl std::error::Error for WrappingError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn built_from_error_chain() {
+        let error = WrappingError(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "refused",
+        ));
+
+        let error_info = ErrorInfo::from_error(&error);
+
+        assert_eq!(error_info.code, "io::ConnectionRefused");
+        assert_eq!(error_info.message, "failed to connect");
+        assert_eq!(error_info.sources, vec!["refused".to_string()]);
+        assert!(error_info.transient);
+        assert_eq!(error_info.occurrences, 1);
+    }
+
+    #[test]
+    fn built_from_state_tracker_error() {
+        let error = error::Error::new(ErrorKind::PermissionDenied, "denied");
+
+        let error_info = ErrorInfo::from_error(&error);
+
+        assert_eq!(error_info.code, "state_tracker::PermissionDenied");
+        assert!(!error_info.transient);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::encoding::Encoding;
+use crate::error::{Error, ErrorKind};
+use crate::output_sink::OutputSink;
+use crate::timestamp::TimestampFormat;
+use crate::tracked_data::TrackedData;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::time::Duration;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::time::Instant;
+
+/// When the written data gets synchronized to the disk.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FsyncPolicy {
+    /// Left to the operating system.
+    Never,
+    /// When the sink is flushed or closed and before rotating the file.
+    #[default]
+    OnFlush,
+    /// After writing every TrackedData.
+    EveryRecord,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct FileSinkConfig {
+    pub path: String,
+    /// Size from which the file gets rotated.
+    #[serde(default)]
+    pub max_size_in_bytes: Option<u64>,
+    /// Time since the file was opened from which it gets rotated.
+    #[serde(default)]
+    pub max_age_in_seconds: Option<u64>,
+    /// Amount of rotated files which are kept, named after the file with
+    /// the suffixes `.1` (the newest) to `.N` (the oldest).
+    #[serde(default = "default_retained_files")]
+    pub retained_files: usize,
+    #[serde(default)]
+    pub fsync_policy: FsyncPolicy,
+}
+
+fn default_retained_files() -> usize {
+    5
+}
+
+impl FileSinkConfig {
+    pub fn new(path: impl Into<String>) -> FileSinkConfig {
+        FileSinkConfig {
+            path: path.into(),
+            max_size_in_bytes: None,
+            max_age_in_seconds: None,
+            retained_files: default_retained_files(),
+            fsync_policy: FsyncPolicy::default(),
+        }
+    }
+}
+
+struct OpenedFile {
+    file: File,
+    size: u64,
+    opened_at: Instant,
+}
+
+/// Appends every TrackedData as a line of JSON to a file, rotating it once
+/// it grows too large or too old.
+pub struct FileSink {
+    config: FileSinkConfig,
+    opened_file: Option<OpenedFile>,
+    timestamp_format: TimestampFormat,
+}
+
+impl FileSink {
+    pub fn new(config: FileSinkConfig) -> Self {
+        Self {
+            config,
+            opened_file: None,
+            timestamp_format: TimestampFormat::default(),
+        }
+    }
+
+    /// Sets the format of the outputs' timestamps, which is the legacy one by default.
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    async fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if let Some(opened_file) = self.opened_file.as_ref() {
+            if self.must_rotate(opened_file, line.len() as u64) {
+                self.rotate().await?;
+            }
+        }
+
+        let opened_file = match self.opened_file.take() {
+            Some(opened_file) => opened_file,
+            None => self.open().await?,
+        };
+        let opened_file = self.opened_file.insert(opened_file);
+
+        opened_file.file.write_all(line).await?;
+        opened_file.size += line.len() as u64;
+
+        if self.config.fsync_policy == FsyncPolicy::EveryRecord {
+            opened_file.file.sync_data().await?;
+        }
+
+        Ok(())
+    }
+
+    fn must_rotate(&self, opened_file: &OpenedFile, additional_size: u64) -> bool {
+        let too_large = match self.config.max_size_in_bytes {
+            Some(max_size) => opened_file.size > 0 && opened_file.size + additional_size > max_size,
+            None => false,
+        };
+
+        let too_old = match self.config.max_age_in_seconds {
+            Some(max_age) => opened_file.opened_at.elapsed() >= Duration::from_secs(max_age),
+            None => false,
+        };
+
+        too_large || too_old
+    }
+
+    async fn open(&self) -> io::Result<OpenedFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)
+            .await?;
+        let size = file.metadata().await?.len();
+
+        Ok(OpenedFile {
+            file,
+            size,
+            opened_at: Instant::now(),
+        })
+    }
+
+    async fn rotate(&mut self) -> io::Result<()> {
+        self.sync().await?;
+        self.opened_file = None;
+
+        let path = &self.config.path;
+        let retained_files = self.config.retained_files;
+
+        if retained_files == 0 {
+            return tokio::fs::remove_file(path).await;
+        }
+
+        remove_if_exists(&format!("{}.{}", path, retained_files)).await?;
+
+        for index in (1..retained_files).rev() {
+            rename_if_exists(
+                &format!("{}.{}", path, index),
+                &format!("{}.{}", path, index + 1),
+            )
+            .await?;
+        }
+
+        tokio::fs::rename(path, format!("{}.1", path)).await
+    }
+
+    async fn sync(&mut self) -> io::Result<()> {
+        if let Some(opened_file) = self.opened_file.as_mut() {
+            opened_file.file.flush().await?;
+
+            if self.config.fsync_policy != FsyncPolicy::Never {
+                opened_file.file.sync_data().await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn remove_if_exists(path: &str) -> io::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+async fn rename_if_exists(from: &str, to: &str) -> io::Result<()> {
+    match tokio::fs::rename(from, to).await {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+#[async_trait]
+impl OutputSink for FileSink {
+    async fn emit(&mut self, tracked_data: &TrackedData) -> Result<(), Error> {
+        let mut line = Encoding::Json.encode(tracked_data, self.timestamp_format)?;
+        line.push(b'\n');
+
+        match self.write(&line).await {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("failed to write to '{}': {}", self.config.path, error),
+            )),
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        match self.sync().await {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("failed to flush '{}': {}", self.config.path, error),
+            )),
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        self.flush().await?;
+        self.opened_file = None;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+    use std::time::SystemTime;
+
+    async fn read_ids(path: &str) -> Vec<String> {
+        tokio::fs::read_to_string(path)
+            .await
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<TrackedData>(line).unwrap().id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rotates_by_size() {
+        const DIRECTORY: &str = "/tmp/cooplan_file_sink_test";
+        const PATH: &str = "/tmp/cooplan_file_sink_test/states.jsonl";
+
+        let _ = tokio::fs::remove_dir_all(DIRECTORY).await;
+        tokio::fs::create_dir_all(DIRECTORY).await.unwrap();
+
+        let record_size = TrackedData::new("0".to_string(), State::Valid, SystemTime::now())
+            .to_json()
+            .unwrap()
+            .len() as u64
+            + 1;
+
+        let mut sink = FileSink::new(FileSinkConfig {
+            max_size_in_bytes: Some(record_size * 2),
+            retained_files: 2,
+            ..FileSinkConfig::new(PATH)
+        });
+
+        for id in 0..7 {
+            sink.emit(&TrackedData::new(
+                id.to_string(),
+                State::Valid,
+                SystemTime::now(),
+            ))
+            .await
+            .unwrap();
+        }
+        sink.close().await.unwrap();
+
+        assert_eq!(read_ids(PATH).await, vec!["6"]);
+        assert_eq!(read_ids(&format!("{}.1", PATH)).await, vec!["4", "5"]);
+        assert_eq!(read_ids(&format!("{}.2", PATH)).await, vec!["2", "3"]);
+        assert!(!tokio::fs::try_exists(format!("{}.3", PATH)).await.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rotates_by_age() {
+        const DIRECTORY: &str = "/tmp/cooplan_file_sink_test_age";
+        const PATH: &str = "/tmp/cooplan_file_sink_test_age/states.jsonl";
+
+        let _ = tokio::fs::remove_dir_all(DIRECTORY).await;
+        tokio::fs::create_dir_all(DIRECTORY).await.unwrap();
+
+        let mut sink = FileSink::new(FileSinkConfig {
+            max_age_in_seconds: Some(60),
+            ..FileSinkConfig::new(PATH)
+        });
+
+        for (id, elapsed_seconds) in [(0, 30), (1, 31), (2, 0)] {
+            sink.emit(&TrackedData::new(
+                id.to_string(),
+                State::Valid,
+                SystemTime::now(),
+            ))
+            .await
+            .unwrap();
+            tokio::time::advance(Duration::from_secs(elapsed_seconds)).await;
+        }
+        sink.close().await.unwrap();
+
+        assert_eq!(read_ids(PATH).await, vec!["2"]);
+        assert_eq!(read_ids(&format!("{}.1", PATH)).await, vec!["0", "1"]);
+    }
+}
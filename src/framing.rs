@@ -0,0 +1,51 @@
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest payload accepted by [`read_frame`].
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Writes the payload preceded by its length, as a big-endian u32.
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    let length = match u32::try_from(payload.len()) {
+        Ok(length) if payload.len() <= MAX_FRAME_SIZE => length,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("frame of {} bytes is too large", payload.len()),
+            ))
+        }
+    };
+
+    // Written from a single buffer, so that a broken connection fails the whole frame
+    // instead of silently swallowing its first part.
+    let mut frame = Vec::with_capacity(payload.len() + 4);
+    frame.extend_from_slice(&length.to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    writer.write_all(&frame).await
+}
+
+/// Reads a payload written by [`write_frame`], returning `None` if the stream
+/// ended before the frame started.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+
+    match reader.read_exact(&mut length).await {
+        Ok(_) => (),
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is too large", length),
+        ));
+    }
+
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload).await?;
+
+    Ok(Some(payload))
+}
@@ -0,0 +1,46 @@
+use std::collections::BTreeMap;
+
+pub const HOSTNAME_LABEL: &str = "hostname";
+pub const PID_LABEL: &str = "pid";
+pub const SERVICE_NAME_LABEL: &str = "service_name";
+pub const SERVICE_VERSION_LABEL: &str = "service_version";
+/// Message of a degraded state, set when it is outputted to receivers which
+/// do not understand degraded states.
+pub const DEGRADED_LABEL: &str = "degraded";
+
+/// Generates the labels identifying the current process.
+///
+/// # Arguments
+/// * `service_name` - Name of the service running in the process.
+/// * `service_version` - Version of the service, usually its `CARGO_PKG_VERSION`.
+pub fn process_labels(
+    service_name: Option<&str>,
+    service_version: Option<&str>,
+) -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+
+    match hostname::get() {
+        Ok(hostname) => {
+            labels.insert(
+                HOSTNAME_LABEL.to_string(),
+                hostname.to_string_lossy().into_owned(),
+            );
+        }
+        Err(error) => log::warn!("failed to retrieve hostname: {}", error),
+    }
+
+    labels.insert(PID_LABEL.to_string(), std::process::id().to_string());
+
+    if let Some(service_name) = service_name {
+        labels.insert(SERVICE_NAME_LABEL.to_string(), service_name.to_string());
+    }
+
+    if let Some(service_version) = service_version {
+        labels.insert(
+            SERVICE_VERSION_LABEL.to_string(),
+            service_version.to_string(),
+        );
+    }
+
+    labels
+}
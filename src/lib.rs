@@ -1,6 +1,33 @@
+mod backoff;
+pub mod compression;
+pub mod encoding;
+pub mod envelope;
 pub mod error;
+pub mod error_info;
+pub mod file_sink;
+pub mod framing;
+pub mod labels;
+pub mod output_config;
+pub mod output_sink;
+pub mod oversize;
+pub mod peer_credentials;
+mod reconnecting_stream;
+pub mod retry;
+pub mod signing;
+mod sink_worker;
+pub mod socket_file;
+pub mod spooling_sink;
 pub mod state;
+pub mod state_collector;
+pub mod state_collector_config;
+pub mod state_registry;
 pub mod state_tracker;
 pub mod state_tracker_client;
 pub mod state_tracking_config;
+pub mod tcp_sink;
+pub mod timestamp;
 pub mod tracked_data;
+pub mod tracker_handle;
+pub mod udp_sink;
+pub mod unix_datagram_sink;
+pub mod unix_stream_sink;
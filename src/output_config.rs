@@ -0,0 +1,101 @@
+use crate::encoding::PayloadFormat;
+use crate::error::Error;
+use crate::file_sink::{FileSink, FileSinkConfig};
+use crate::output_sink::OutputSink;
+use crate::oversize::OversizeConfig;
+use crate::tcp_sink::TcpSink;
+use crate::udp_sink::UdpSink;
+use crate::unix_datagram_sink::UnixDatagramSink;
+use crate::unix_stream_sink::UnixStreamSink;
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::net::UnixDatagram;
+
+/// Destination to which a StateTracker outputs, besides or instead of `state_output_receiver_path`.
+///
+/// Serialized with a `type` field naming the variant, e.g.
+/// `{"type": "tcp", "address": "collector:7070"}`.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputConfig {
+    /// UnixDatagram socket, receiving datagrams sent from `state_output_sender_path`,
+    /// or from an unbound socket when it is missing.
+    UnixDatagram { receiver_path: String },
+    /// UnixStream socket, receiving length-prefixed frames.
+    UnixStream { receiver_path: String },
+    /// TCP connection, receiving length-prefixed frames.
+    Tcp { address: String },
+    /// UDP socket, receiving a datagram per TrackedData.
+    Udp { address: String },
+    /// File to which JSON lines are appended, regardless of the payload format.
+    File(FileSinkConfig),
+}
+
+impl OutputConfig {
+    /// Builds the sink outputting to the destination.
+    ///
+    /// # Arguments
+    /// * `output_sender` - Socket sending the UnixDatagram outputs.
+    /// * `payload_format` - How the outputs are serialized, files being always written
+    ///   as JSON lines.
+    /// * `oversize_config` - Handling of the outputs which do not fit into a datagram.
+    pub fn build_sink(
+        &self,
+        output_sender: &Arc<UnixDatagram>,
+        payload_format: PayloadFormat,
+        oversize_config: OversizeConfig,
+    ) -> Result<Box<dyn OutputSink>, Error> {
+        match self {
+            OutputConfig::UnixDatagram { receiver_path } => Ok(Box::new(
+                UnixDatagramSink::new(output_sender.clone(), receiver_path)
+                    .with_payload_format(payload_format)
+                    .with_oversize_config(oversize_config),
+            )),
+            OutputConfig::UnixStream { receiver_path } => Ok(Box::new(
+                UnixStreamSink::new(receiver_path).with_payload_format(payload_format),
+            )),
+            OutputConfig::Tcp { address } => Ok(Box::new(
+                TcpSink::new(address).with_payload_format(payload_format),
+            )),
+            OutputConfig::Udp { address } => Ok(Box::new(
+                UdpSink::new(address)
+                    .with_payload_format(payload_format)
+                    .with_oversize_config(oversize_config),
+            )),
+            OutputConfig::File(file_sink_config) => Ok(Box::new(
+                FileSink::new(file_sink_config.clone())
+                    .with_timestamp_format(payload_format.timestamp_format),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserializes_tagged_outputs() {
+        let output_configs: Vec<OutputConfig> = serde_json::from_str(
+            r#"[
+                {"type": "unix_stream", "receiver_path": "/tmp/receiver.sock"},
+                {"type": "file", "path": "/tmp/states.jsonl", "max_size_in_bytes": 1024}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            output_configs,
+            vec![
+                OutputConfig::UnixStream {
+                    receiver_path: "/tmp/receiver.sock".to_string()
+                },
+                OutputConfig::File(FileSinkConfig {
+                    max_size_in_bytes: Some(1024),
+                    ..FileSinkConfig::new("/tmp/states.jsonl")
+                }),
+            ]
+        );
+    }
+}
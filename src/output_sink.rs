@@ -0,0 +1,45 @@
+use crate::error::Error;
+use crate::tracked_data::TrackedData;
+
+use async_trait::async_trait;
+
+/// Destination to which a StateTracker outputs the TrackedData it receives.
+#[async_trait]
+pub trait OutputSink: Send {
+    async fn emit(&mut self, tracked_data: &TrackedData) -> Result<(), Error>;
+
+    /// Makes sure that the data emitted so far has been delivered.
+    async fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Releases the resources of the sink, which is not used anymore afterwards.
+    async fn close(&mut self) -> Result<(), Error> {
+        self.flush().await
+    }
+
+    /// Completes once the sink must be flushed without waiting for the next emission,
+    /// e.g. to replay the data which it could not deliver. Never completes by default.
+    async fn flush_due(&mut self) {
+        std::future::pending().await
+    }
+}
+
+#[async_trait]
+impl<S: OutputSink + ?Sized> OutputSink for Box<S> {
+    async fn emit(&mut self, tracked_data: &TrackedData) -> Result<(), Error> {
+        (**self).emit(tracked_data).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        (**self).flush().await
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        (**self).close().await
+    }
+
+    async fn flush_due(&mut self) {
+        (**self).flush_due().await
+    }
+}
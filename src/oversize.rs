@@ -0,0 +1,532 @@
+//! Handling of the encoded TrackedData which is larger than a datagram can be.
+
+use crate::compression;
+use crate::compression::CompressionAlgorithm;
+use crate::encoding::PayloadFormat;
+use crate::error::{Error, ErrorKind};
+use crate::state::State;
+use crate::tracked_data::TrackedData;
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+pub(crate) const FRAGMENT_TAG: u8 = 0x20;
+
+/// Tag, message id, fragment index and fragment count.
+const FRAGMENT_HEADER_SIZE: usize = 1 + 8 + 2 + 2;
+
+/// Appended to whatever gets truncated.
+pub const TRUNCATION_MARKER: &str = "[truncated]";
+
+/// Amount of partially received messages from which the oldest one gets dropped.
+const MAX_PENDING_MESSAGES: usize = 64;
+
+/// Largest size of the messages which get reassembled.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizePolicy {
+    /// Drops the error's backtrace and sources, then truncates the state's and the
+    /// error's messages, marking them with [`TRUNCATION_MARKER`].
+    #[default]
+    Truncate,
+    /// Compresses the payload with deflate unless it already was by the configured
+    /// compression, failing if it is still too large.
+    Compress,
+    /// Splits the payload into numbered fragments, which are reassembled by the
+    /// StateCollector.
+    Fragment,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct OversizeConfig {
+    /// Size of the datagrams from which the policy is applied. StateCollectors skip
+    /// datagrams larger than [`crate::state_collector::MAX_DATAGRAM_SIZE`].
+    #[serde(default = "default_max_datagram_size_in_bytes")]
+    pub max_datagram_size_in_bytes: usize,
+    #[serde(default)]
+    pub policy: OversizePolicy,
+}
+
+/// Largest UDP payload over IPv4, which Unix datagram sockets accept as well.
+fn default_max_datagram_size_in_bytes() -> usize {
+    65507
+}
+
+impl Default for OversizeConfig {
+    fn default() -> Self {
+        OversizeConfig {
+            max_datagram_size_in_bytes: default_max_datagram_size_in_bytes(),
+            policy: OversizePolicy::default(),
+        }
+    }
+}
+
+impl OversizeConfig {
+    /// Serializes the data into the datagrams to send, applying the policy if it
+    /// does not fit into a single one.
+    ///
+    /// The policy works on the uncompressed encoding, so that the payload gets
+    /// compressed at most once, and every datagram gets signed last, fragments included.
+    pub(crate) fn fit(
+        &self,
+        tracked_data: &TrackedData,
+        payload_format: &PayloadFormat,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let max_size = self
+            .max_datagram_size_in_bytes
+            .saturating_sub(payload_format.signature_size());
+
+        let encoded = payload_format.encode(tracked_data)?;
+        let compressed = payload_format.compress(&encoded)?;
+        let payload = compressed.as_deref().unwrap_or(&encoded);
+        if payload.len() <= max_size {
+            return Ok(vec![payload_format.sign(payload)]);
+        }
+
+        log::warn!(
+            "applying the {:?} policy to a payload of {} bytes",
+            self.policy,
+            payload.len()
+        );
+
+        let payloads = match self.policy {
+            OversizePolicy::Truncate => {
+                vec![self.truncate(tracked_data, payload_format, max_size)?]
+            }
+            OversizePolicy::Compress => match compressed {
+                Some(compressed) => vec![compressed],
+                None => vec![compression::compress(
+                    CompressionAlgorithm::Deflate,
+                    &encoded,
+                )?],
+            },
+            OversizePolicy::Fragment => fragment(payload, max_size)?,
+        };
+
+        payloads
+            .iter()
+            .map(|payload| {
+                single_datagram(
+                    payload_format.sign(payload),
+                    self.max_datagram_size_in_bytes,
+                )
+            })
+            .collect()
+    }
+
+    /// Truncates the data until its uncompressed encoding fits into `max_size`, as
+    /// the truncated bytes are uncompressed ones, then compresses it.
+    fn truncate(
+        &self,
+        tracked_data: &TrackedData,
+        payload_format: &PayloadFormat,
+        max_size: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let mut truncated = without_error_details(tracked_data);
+        let mut encoded = payload_format.encode(&truncated)?;
+        let mut compressed = payload_format.compress(&encoded)?;
+
+        // Re-encoding after every message, so that the second one is only
+        // truncated if the first one was not enough.
+        for message_index in 0..TRUNCATED_MESSAGES {
+            if compressed.as_ref().unwrap_or(&encoded).len() <= max_size {
+                break;
+            }
+
+            if let Some(message) = truncated_message(&mut truncated, message_index) {
+                truncate_message(message, encoded.len().saturating_sub(max_size));
+                encoded = payload_format.encode(&truncated)?;
+                compressed = payload_format.compress(&encoded)?;
+            }
+        }
+
+        Ok(compressed.unwrap_or(encoded))
+    }
+}
+
+fn single_datagram(payload: Vec<u8>, max_size: usize) -> Result<Vec<u8>, Error> {
+    if payload.len() > max_size {
+        return Err(Error::new(
+            ErrorKind::InternalFailure,
+            format!(
+                "payload of {} bytes does not fit into a datagram",
+                payload.len()
+            ),
+        ));
+    }
+
+    Ok(payload)
+}
+
+/// Splits the payload into fragments of at most `max_size` bytes.
+fn fragment(payload: &[u8], max_size: usize) -> Result<Vec<Vec<u8>>, Error> {
+    let chunk_size = max_size.saturating_sub(FRAGMENT_HEADER_SIZE);
+    if chunk_size == 0 {
+        return Err(Error::new(
+            ErrorKind::InternalFailure,
+            "datagrams are too small to hold fragments",
+        ));
+    }
+
+    let count = match u16::try_from(payload.len().div_ceil(chunk_size)) {
+        Ok(count) => count,
+        Err(_) => {
+            return Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("payload of {} bytes has too many fragments", payload.len()),
+            ))
+        }
+    };
+
+    let message_id = fastrand::u64(..);
+
+    Ok(payload
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+            fragment.push(FRAGMENT_TAG);
+            fragment.extend_from_slice(&message_id.to_be_bytes());
+            fragment.extend_from_slice(&(index as u16).to_be_bytes());
+            fragment.extend_from_slice(&count.to_be_bytes());
+            fragment.extend_from_slice(chunk);
+            fragment
+        })
+        .collect())
+}
+
+fn without_error_details(tracked_data: &TrackedData) -> TrackedData {
+    let mut truncated = tracked_data.clone();
+
+    if let Some(error_info) = truncated.error_info.as_mut() {
+        if error_info.backtrace.is_some() {
+            error_info.backtrace = Some(TRUNCATION_MARKER.to_string());
+        }
+        if !error_info.sources.is_empty() {
+            error_info.sources = vec![TRUNCATION_MARKER.to_string()];
+        }
+    }
+
+    truncated
+}
+
+/// Amount of messages which can be truncated, see [`truncated_message`].
+const TRUNCATED_MESSAGES: usize = 2;
+
+/// Message which gets truncated at the index, the state's one first and the error's one then.
+fn truncated_message(tracked_data: &mut TrackedData, index: usize) -> Option<&mut String> {
+    match index {
+        0 => match &mut tracked_data.state {
+            State::Error(message) | State::Degraded(message) => Some(message),
+            _ => None,
+        },
+        _ => tracked_data
+            .error_info
+            .as_mut()
+            .map(|error_info| &mut error_info.message),
+    }
+}
+
+fn truncate_message(message: &mut String, excess: usize) {
+    // Such messages would grow by being marked.
+    if message.len() <= TRUNCATION_MARKER.len() {
+        return;
+    }
+
+    let mut length = message
+        .len()
+        .saturating_sub(excess + TRUNCATION_MARKER.len());
+    while !message.is_char_boundary(length) {
+        length -= 1;
+    }
+
+    message.truncate(length);
+    message.push_str(TRUNCATION_MARKER);
+}
+
+struct PartialMessage {
+    // Filled as the fragments arrive, rather than allocated from their claimed count.
+    fragments: BTreeMap<u16, Vec<u8>>,
+    count: u16,
+    size: usize,
+}
+
+/// Reassembles the payloads which were split into fragments.
+#[derive(Default)]
+pub struct Reassembler {
+    partial_messages: HashMap<u64, PartialMessage>,
+    // Ids of the partial messages, from the oldest to the newest.
+    message_ids: VecDeque<u64>,
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler::default()
+    }
+
+    /// Returns the payload as is if it is not a fragment, the reassembled payload if
+    /// it is the last missing fragment of a message, or `None` otherwise.
+    pub fn push<'a>(&mut self, payload: &'a [u8]) -> Result<Option<Cow<'a, [u8]>>, Error> {
+        if payload.first() != Some(&FRAGMENT_TAG) {
+            return Ok(Some(Cow::Borrowed(payload)));
+        }
+
+        if payload.len() < FRAGMENT_HEADER_SIZE {
+            return Err(Error::new(ErrorKind::InternalFailure, "truncated fragment"));
+        }
+
+        let message_id = u64::from_be_bytes(payload[1..9].try_into().unwrap_or_default());
+        let index = u16::from_be_bytes([payload[9], payload[10]]);
+        let count = u16::from_be_bytes([payload[11], payload[12]]);
+        let chunk = &payload[FRAGMENT_HEADER_SIZE..];
+
+        if index >= count || chunk.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("invalid fragment {} out of {}", index, count),
+            ));
+        }
+
+        if !self.partial_messages.contains_key(&message_id) {
+            if self.message_ids.len() >= MAX_PENDING_MESSAGES {
+                if let Some(oldest_id) = self.message_ids.pop_front() {
+                    self.partial_messages.remove(&oldest_id);
+                    log::warn!("dropped incomplete message {}", oldest_id);
+                }
+            }
+
+            self.message_ids.push_back(message_id);
+            self.partial_messages.insert(
+                message_id,
+                PartialMessage {
+                    fragments: BTreeMap::new(),
+                    count,
+                    size: 0,
+                },
+            );
+        }
+
+        let Some(partial_message) = self.partial_messages.get_mut(&message_id) else {
+            return Ok(None);
+        };
+
+        if partial_message.count != count || partial_message.size + chunk.len() > MAX_MESSAGE_SIZE {
+            self.remove(message_id);
+            return Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("inconsistent fragments of message {}", message_id),
+            ));
+        }
+
+        if let Entry::Vacant(entry) = partial_message.fragments.entry(index) {
+            entry.insert(chunk.to_vec());
+            partial_message.size += chunk.len();
+        }
+
+        if partial_message.fragments.len() < count as usize {
+            return Ok(None);
+        }
+
+        let reassembled = self
+            .remove(message_id)
+            .map(|partial_message| partial_message.fragments.into_values().flatten().collect());
+
+        Ok(reassembled.map(Cow::Owned))
+    }
+
+    fn remove(&mut self, message_id: u64) -> Option<PartialMessage> {
+        self.message_ids.retain(|id| *id != message_id);
+        self.partial_messages.remove(&message_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::CompressionConfig;
+    use crate::encoding;
+    use crate::error_info::ErrorInfo;
+    use std::time::SystemTime;
+
+    fn large_error() -> TrackedData {
+        let mut tracked_data = TrackedData::new(
+            "test_id".to_string(),
+            State::Error("é".repeat(1000)),
+            SystemTime::now(),
+        );
+        let mut error_info = ErrorInfo::new("code", "message");
+        error_info.backtrace = Some("frame\n".repeat(1000));
+        tracked_data.error_info = Some(error_info);
+
+        tracked_data
+    }
+
+    fn fit(policy: OversizePolicy, tracked_data: &TrackedData) -> Result<Vec<Vec<u8>>, Error> {
+        fit_with_format(policy, &PayloadFormat::default(), tracked_data)
+    }
+
+    fn fit_with_format(
+        policy: OversizePolicy,
+        payload_format: &PayloadFormat,
+        tracked_data: &TrackedData,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let oversize_config = OversizeConfig {
+            max_datagram_size_in_bytes: 512,
+            policy,
+        };
+
+        oversize_config.fit(tracked_data, payload_format)
+    }
+
+    fn compressing_format(threshold_in_bytes: usize) -> PayloadFormat {
+        PayloadFormat {
+            compression: Some(CompressionConfig {
+                threshold_in_bytes,
+                ..CompressionConfig::default()
+            }),
+            ..PayloadFormat::default()
+        }
+    }
+
+    #[test]
+    fn truncates_details_then_messages() {
+        let tracked_data = large_error();
+
+        let datagrams = fit(OversizePolicy::Truncate, &tracked_data).unwrap();
+        assert_eq!(datagrams.len(), 1);
+        assert!(datagrams[0].len() <= 512);
+
+        let truncated = encoding::decode(&datagrams[0]).unwrap();
+        let State::Error(message) = truncated.state else {
+            panic!("state should still be an error");
+        };
+        assert!(message.starts_with('é'));
+        assert!(message.ends_with(TRUNCATION_MARKER));
+
+        let error_info = truncated.error_info.unwrap();
+        assert_eq!(error_info.backtrace.unwrap(), TRUNCATION_MARKER);
+    }
+
+    #[test]
+    fn truncates_second_message_only_as_needed() {
+        let mut tracked_data = TrackedData::new(
+            "test_id".to_string(),
+            State::Error("a".repeat(1000)),
+            SystemTime::now(),
+        );
+        tracked_data.error_info = Some(ErrorInfo::new("code", "b".repeat(1000)));
+
+        let datagrams = fit(OversizePolicy::Truncate, &tracked_data).unwrap();
+        assert!(datagrams[0].len() <= 512);
+
+        let truncated = encoding::decode(&datagrams[0]).unwrap();
+        assert_eq!(truncated.state, State::Error(TRUNCATION_MARKER.to_string()));
+
+        let message = truncated.error_info.unwrap().message;
+        assert!(message.starts_with('b'));
+        assert!(message.ends_with(TRUNCATION_MARKER));
+    }
+
+    #[test]
+    fn compresses_payload() {
+        let tracked_data = large_error();
+
+        let datagrams = fit(OversizePolicy::Compress, &tracked_data).unwrap();
+        assert_eq!(datagrams.len(), 1);
+
+        let decompressed = encoding::decode(&datagrams[0]).unwrap();
+        assert_eq!(decompressed.state, tracked_data.state);
+        assert_eq!(decompressed.error_info, tracked_data.error_info);
+    }
+
+    #[test]
+    fn truncates_compressed_payloads_by_their_uncompressed_size() {
+        // Barely compressible, so that the compressed payload is still too large.
+        let message: String = std::iter::repeat_with(fastrand::alphanumeric)
+            .take(2000)
+            .collect();
+        let tracked_data = TrackedData::new(
+            "test_id".to_string(),
+            State::Error(message),
+            SystemTime::now(),
+        );
+
+        let datagrams = fit_with_format(
+            OversizePolicy::Truncate,
+            &compressing_format(0),
+            &tracked_data,
+        )
+        .unwrap();
+        assert!(datagrams[0].len() <= 512);
+
+        let truncated = encoding::decode(&datagrams[0]).unwrap();
+        let State::Error(message) = truncated.state else {
+            panic!("state should still be an error");
+        };
+        assert!(message.ends_with(TRUNCATION_MARKER));
+    }
+
+    #[test]
+    fn compresses_payloads_left_uncompressed_by_the_configuration() {
+        let tracked_data = large_error();
+
+        // The configured compression does not apply to such small payloads.
+        let datagrams = fit_with_format(
+            OversizePolicy::Compress,
+            &compressing_format(usize::MAX),
+            &tracked_data,
+        )
+        .unwrap();
+        let decompressed = encoding::decode(&datagrams[0]).unwrap();
+        assert_eq!(decompressed.error_info, tracked_data.error_info);
+    }
+
+    #[test]
+    fn reassembles_fragments_in_any_order() {
+        let tracked_data = large_error();
+
+        let mut datagrams = fit(OversizePolicy::Fragment, &tracked_data).unwrap();
+        assert!(datagrams.len() > 1);
+        assert!(datagrams.iter().all(|datagram| datagram.len() <= 512));
+
+        let last_datagram = datagrams.remove(1);
+        datagrams.reverse();
+
+        let mut reassembler = Reassembler::new();
+        for datagram in datagrams.iter() {
+            assert!(reassembler.push(datagram).unwrap().is_none());
+        }
+        // Duplicated fragments are ignored.
+        assert!(reassembler.push(&datagrams[0]).unwrap().is_none());
+
+        let reassembled = reassembler.push(&last_datagram).unwrap().unwrap();
+        let reassembled = encoding::decode(&reassembled).unwrap();
+        assert_eq!(reassembled.state, tracked_data.state);
+        assert_eq!(reassembled.error_info, tracked_data.error_info);
+    }
+
+    #[test]
+    fn rejects_invalid_fragments() {
+        let fragment = |index: u16, count: u16, chunk: &[u8]| {
+            let mut fragment = vec![FRAGMENT_TAG];
+            fragment.extend_from_slice(&1u64.to_be_bytes());
+            fragment.extend_from_slice(&index.to_be_bytes());
+            fragment.extend_from_slice(&count.to_be_bytes());
+            fragment.extend_from_slice(chunk);
+            fragment
+        };
+
+        let mut reassembler = Reassembler::new();
+        assert!(reassembler.push(&fragment(0, u16::MAX, b"")).is_err());
+        assert!(reassembler.push(&fragment(2, 2, b"chunk")).is_err());
+        assert!(reassembler
+            .push(&fragment(0, u16::MAX, b"chunk"))
+            .unwrap()
+            .is_none());
+        assert!(reassembler.push(&fragment(1, 2, b"chunk")).is_err());
+    }
+}
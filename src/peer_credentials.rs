@@ -0,0 +1,80 @@
+//! Credentials of the processes sending datagrams to a collector, as reported
+//! by the kernel through `SCM_CREDENTIALS`, which is only supported on Linux.
+
+use std::io;
+use tokio::net::UnixDatagram;
+
+/// Process which sent a datagram, which cannot be forged by the process itself.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PeerCredentials {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Makes the kernel attach the sender's credentials to every datagram received by the socket.
+#[cfg(target_os = "linux")]
+pub(crate) fn enable(socket: &UnixDatagram) -> io::Result<()> {
+    nix::sys::socket::setsockopt(socket, nix::sys::socket::sockopt::PassCred, &true)
+        .map_err(io::Error::from)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn enable(_: &UnixDatagram) -> io::Result<()> {
+    Ok(())
+}
+
+/// Receives a datagram along with the credentials of its sender, if the kernel reported them.
+///
+/// Fails with [`io::ErrorKind::InvalidData`] if the datagram did not fit into the buffer,
+/// in which case it was consumed all the same.
+#[cfg(target_os = "linux")]
+pub(crate) async fn recv(
+    socket: &UnixDatagram,
+    buffer: &mut [u8],
+) -> io::Result<(usize, Option<PeerCredentials>)> {
+    use nix::sys::socket::{ControlMessageOwned, MsgFlags, UnixCredentials};
+    use std::io::IoSliceMut;
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    socket
+        .async_io(Interest::READABLE, || {
+            let mut iov = [IoSliceMut::new(buffer)];
+            let mut cmsg_buffer = nix::cmsg_space!(UnixCredentials);
+
+            let message = nix::sys::socket::recvmsg::<()>(
+                socket.as_raw_fd(),
+                &mut iov,
+                Some(&mut cmsg_buffer),
+                MsgFlags::MSG_DONTWAIT,
+            )?;
+
+            let credentials = message.cmsgs()?.find_map(|cmsg| match cmsg {
+                ControlMessageOwned::ScmCredentials(credentials) => Some(PeerCredentials {
+                    pid: credentials.pid(),
+                    uid: credentials.uid(),
+                    gid: credentials.gid(),
+                }),
+                _ => None,
+            });
+
+            if message.flags.contains(MsgFlags::MSG_TRUNC) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("datagram truncated to {} bytes", message.bytes),
+                ));
+            }
+
+            Ok((message.bytes, credentials))
+        })
+        .await
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) async fn recv(
+    socket: &UnixDatagram,
+    buffer: &mut [u8],
+) -> io::Result<(usize, Option<PeerCredentials>)> {
+    Ok((socket.recv(buffer).await?, None))
+}
@@ -0,0 +1,194 @@
+use crate::backoff::Backoff;
+use crate::error::{Error, ErrorKind};
+use crate::framing;
+
+use futures::future::BoxFuture;
+use nix::errno::Errno;
+use nix::sys::socket::MsgFlags;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// Longest wait for a connection, much shorter than the kernel's own timeout so that
+/// an unreachable peer does not stall the sink.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Connect<S> = Box<dyn Fn() -> BoxFuture<'static, io::Result<S>> + Send + Sync>;
+
+/// Stream which gets connected lazily and reconnected whenever writing to it fails,
+/// waiting longer after every failed connection attempt.
+pub(crate) struct ReconnectingStream<S> {
+    peer: String,
+    connect: Connect<S>,
+    stream: Option<S>,
+    backoff: Backoff,
+    next_connection_attempt: Option<Instant>,
+}
+
+impl<S: AsyncWrite + AsRawFd + Unpin + Send> ReconnectingStream<S> {
+    /// # Arguments
+    /// * `peer` - Description of the peer, used for logging.
+    /// * `connect` - Opens a new stream to the peer.
+    pub(crate) fn new(peer: String, connect: Connect<S>) -> ReconnectingStream<S> {
+        ReconnectingStream {
+            peer,
+            connect,
+            stream: None,
+            backoff: Backoff::new(INITIAL_RECONNECT_DELAY, MAX_RECONNECT_DELAY),
+            next_connection_attempt: None,
+        }
+    }
+
+    /// Writes the payload as a frame, reconnecting once if the current connection is broken.
+    ///
+    /// A connection closed by the peer is replaced before writing, since the first
+    /// write to it usually succeeds while the frame never reaches the peer.
+    pub(crate) async fn write_frame(&mut self, payload: &[u8]) -> Result<(), Error> {
+        loop {
+            let (mut stream, reconnected) = match self.stream.take() {
+                Some(stream) if !is_closed(&stream) => (stream, false),
+                Some(_) => {
+                    log::warn!("connection to '{}' was closed, reconnecting", self.peer);
+
+                    (self.connect().await?, true)
+                }
+                None => (self.connect().await?, true),
+            };
+
+            match framing::write_frame(&mut stream, payload).await {
+                Ok(_) => {
+                    self.stream = Some(stream);
+                    return Ok(());
+                }
+                Err(error) => {
+                    if reconnected {
+                        return Err(Error::new(
+                            ErrorKind::InternalFailure,
+                            format!("failed to write to '{}': {}", self.peer, error),
+                        ));
+                    }
+
+                    log::warn!(
+                        "connection to '{}' broke, reconnecting: {}",
+                        self.peer,
+                        error
+                    );
+                }
+            }
+        }
+    }
+
+    pub(crate) async fn flush(&mut self) -> Result<(), Error> {
+        if let Some(stream) = self.stream.as_mut() {
+            if let Err(error) = stream.flush().await {
+                self.stream = None;
+
+                return Err(Error::new(
+                    ErrorKind::InternalFailure,
+                    format!("failed to flush '{}': {}", self.peer, error),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn close(&mut self) -> Result<(), Error> {
+        if let Some(mut stream) = self.stream.take() {
+            if let Err(error) = stream.shutdown().await {
+                return Err(Error::new(
+                    ErrorKind::InternalFailure,
+                    format!("failed to close '{}': {}", self.peer, error),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn connect(&mut self) -> Result<S, Error> {
+        if let Some(next_connection_attempt) = self.next_connection_attempt {
+            if Instant::now() < next_connection_attempt {
+                return Err(Error::new(
+                    ErrorKind::InternalFailure,
+                    format!("not connected to '{}', waiting to reconnect", self.peer),
+                ));
+            }
+        }
+
+        let connection = match tokio::time::timeout(CONNECT_TIMEOUT, (self.connect)()).await {
+            Ok(connection) => connection,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "connection timed out",
+            )),
+        };
+
+        match connection {
+            Ok(stream) => {
+                log::info!("connected to '{}'", self.peer);
+
+                self.backoff.reset();
+                self.next_connection_attempt = None;
+
+                Ok(stream)
+            }
+            Err(error) => {
+                let delay = self.backoff.next_delay();
+                self.next_connection_attempt = Some(Instant::now() + delay);
+
+                Err(Error::new(
+                    ErrorKind::InternalFailure,
+                    format!(
+                        "failed to connect to '{}', retrying in {:?}: {}",
+                        self.peer, delay, error
+                    ),
+                ))
+            }
+        }
+    }
+}
+
+/// Whether the peer closed or reset the connection, checked without blocking and
+/// without consuming anything, as the peer is never expected to write.
+fn is_closed(stream: &impl AsRawFd) -> bool {
+    let mut buffer = [0; 1];
+
+    match nix::sys::socket::recv(
+        stream.as_raw_fd(),
+        &mut buffer,
+        MsgFlags::MSG_PEEK | MsgFlags::MSG_DONTWAIT,
+    ) {
+        Ok(0) => true,
+        Ok(_) | Err(Errno::EAGAIN) | Err(Errno::EINTR) => false,
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpStream;
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_connecting_and_waits_to_reconnect() {
+        let mut stream: ReconnectingStream<TcpStream> = ReconnectingStream::new(
+            "unreachable".to_string(),
+            Box::new(|| Box::pin(futures::future::pending())),
+        );
+
+        let started_at = Instant::now();
+        let error = stream.write_frame(b"payload").await.unwrap_err();
+        assert!(error.message.contains("timed out"));
+        assert_eq!(started_at.elapsed(), CONNECT_TIMEOUT);
+
+        // The failed attempt counts for the backoff, so no connection is attempted yet.
+        let error = stream.write_frame(b"payload").await.unwrap_err();
+        assert!(error.message.contains("waiting to reconnect"));
+        assert_eq!(started_at.elapsed(), CONNECT_TIMEOUT);
+    }
+}
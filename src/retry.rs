@@ -0,0 +1,283 @@
+use crate::backoff::Backoff;
+use crate::output_sink::OutputSink;
+use crate::tracked_data::TrackedData;
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct RetryConfig {
+    /// Amount of data waiting to be retried from which the oldest gets dropped,
+    /// urgent data such as errors only once nothing else is left to drop.
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    #[serde(default = "default_initial_delay_in_milliseconds")]
+    pub initial_delay_in_milliseconds: u64,
+    #[serde(default = "default_max_delay_in_milliseconds")]
+    pub max_delay_in_milliseconds: u64,
+}
+
+fn default_capacity() -> usize {
+    256
+}
+
+fn default_initial_delay_in_milliseconds() -> u64 {
+    100
+}
+
+fn default_max_delay_in_milliseconds() -> u64 {
+    5000
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            capacity: default_capacity(),
+            initial_delay_in_milliseconds: default_initial_delay_in_milliseconds(),
+            max_delay_in_milliseconds: default_max_delay_in_milliseconds(),
+        }
+    }
+}
+
+/// Counters of the data whose emission failed, shared by every sink of a StateTracker.
+#[derive(Default, Debug)]
+pub struct RetryStats {
+    retried: AtomicU64,
+    coalesced: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl RetryStats {
+    /// Data delivered after failing at least once.
+    pub fn retried(&self) -> u64 {
+        self.retried.load(Ordering::Relaxed)
+    }
+
+    /// Data replaced by newer data of the same id while waiting to be retried.
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+
+    /// Data which was never delivered, either because the retry queue was full
+    /// or because the tracker stopped.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Data waiting to be emitted again by a sink, after its emission failed.
+pub(crate) struct RetryQueue {
+    pending: VecDeque<Arc<TrackedData>>,
+    capacity: usize,
+    backoff: Backoff,
+    next_attempt: Instant,
+    stats: Arc<RetryStats>,
+}
+
+impl RetryQueue {
+    pub(crate) fn new(retry_config: &RetryConfig, stats: Arc<RetryStats>) -> RetryQueue {
+        RetryQueue {
+            pending: VecDeque::new(),
+            capacity: retry_config.capacity,
+            backoff: Backoff::new(
+                Duration::from_millis(retry_config.initial_delay_in_milliseconds),
+                Duration::from_millis(retry_config.max_delay_in_milliseconds),
+            )
+            .with_jitter(),
+            next_attempt: Instant::now(),
+            stats,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Queues the data behind any other pending data, replacing the pending
+    /// data of the same id unless it is urgent, e.g. an error.
+    ///
+    /// Heartbeats are never queued, since they are outdated once retried.
+    pub(crate) fn push(&mut self, tracked_data: Arc<TrackedData>) {
+        if tracked_data.heartbeat {
+            return;
+        }
+
+        if self.capacity == 0 {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        if self.pending.is_empty() {
+            self.next_attempt = Instant::now() + self.backoff.next_delay();
+        }
+
+        let pending = self.pending.len();
+        self.pending
+            .retain(|pending| pending.id != tracked_data.id || pending.state.is_urgent());
+        self.stats
+            .coalesced
+            .fetch_add((pending - self.pending.len()) as u64, Ordering::Relaxed);
+
+        if self.pending.len() >= self.capacity {
+            let oldest_index = self
+                .pending
+                .iter()
+                .position(|pending| !pending.state.is_urgent())
+                .unwrap_or(0);
+            self.pending.remove(oldest_index);
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            log::warn!("dropped the oldest data waiting to be retried");
+        }
+
+        self.pending.push_back(tracked_data);
+    }
+
+    /// Completes once the pending data should be retried.
+    pub(crate) async fn wait(&self) {
+        if self.pending.is_empty() {
+            std::future::pending::<()>().await;
+        }
+
+        tokio::time::sleep_until(self.next_attempt).await;
+    }
+
+    /// Emits the pending data in order, stopping at the first failure.
+    pub(crate) async fn retry(&mut self, sink: &mut dyn OutputSink) {
+        while let Some(tracked_data) = self.pending.front() {
+            if let Err(error) = sink.emit(tracked_data).await {
+                let delay = self.backoff.next_delay();
+                self.next_attempt = Instant::now() + delay;
+
+                log::warn!(
+                    "failed to retry output of {} records, retrying in {:?}: {}",
+                    self.pending.len(),
+                    delay,
+                    error
+                );
+                return;
+            }
+
+            self.pending.pop_front();
+            self.stats.retried.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.backoff.reset();
+    }
+
+    /// Gives up on the pending data.
+    pub(crate) fn abandon(&mut self) {
+        if !self.pending.is_empty() {
+            log::error!(
+                "dropped {} records which could not be outputted",
+                self.pending.len()
+            );
+        }
+
+        self.stats
+            .dropped
+            .fetch_add(self.pending.len() as u64, Ordering::Relaxed);
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+    use std::time::SystemTime;
+
+    fn tracked_data(id: &str, state: State) -> Arc<TrackedData> {
+        Arc::new(TrackedData::new(id.to_string(), state, SystemTime::now()))
+    }
+
+    #[test]
+    fn coalesces_all_but_urgent_data() {
+        let stats = Arc::new(RetryStats::default());
+        let mut retry_queue = RetryQueue::new(&RetryConfig::default(), stats.clone());
+
+        retry_queue.push(tracked_data("A", State::Valid));
+        retry_queue.push(tracked_data("B", State::Valid));
+        retry_queue.push(tracked_data("A", State::Error("error".to_string())));
+        retry_queue.push(tracked_data("A", State::Valid));
+        retry_queue.push(tracked_data("A", State::Idle));
+
+        let pending: Vec<(&str, &State)> = retry_queue
+            .pending
+            .iter()
+            .map(|tracked_data| (tracked_data.id.as_str(), &tracked_data.state))
+            .collect();
+
+        assert_eq!(
+            pending,
+            vec![
+                ("B", &State::Valid),
+                ("A", &State::Error("error".to_string())),
+                ("A", &State::Idle)
+            ]
+        );
+        assert_eq!(stats.coalesced(), 2);
+    }
+
+    #[test]
+    fn drops_oldest_data_when_full() {
+        let stats = Arc::new(RetryStats::default());
+        let mut retry_queue = RetryQueue::new(
+            &RetryConfig {
+                capacity: 2,
+                ..RetryConfig::default()
+            },
+            stats.clone(),
+        );
+
+        for id in ["A", "B", "C"] {
+            retry_queue.push(tracked_data(id, State::Valid));
+        }
+
+        assert_eq!(retry_queue.pending.front().unwrap().id, "B");
+        assert_eq!(stats.dropped(), 1);
+
+        // Errors are kept over older data which is not urgent.
+        retry_queue.abandon();
+        retry_queue.push(tracked_data("A", State::Error("error".to_string())));
+        retry_queue.push(tracked_data("B", State::Valid));
+        retry_queue.push(tracked_data("C", State::Valid));
+
+        let ids: Vec<&str> = retry_queue
+            .pending
+            .iter()
+            .map(|pending| pending.id.as_str())
+            .collect();
+        assert_eq!(ids, ["A", "C"]);
+
+        // Unless every pending data is urgent.
+        retry_queue.push(tracked_data("B", State::Error("error".to_string())));
+        retry_queue.push(tracked_data("C", State::Error("error".to_string())));
+        assert_eq!(retry_queue.pending.front().unwrap().id, "B");
+
+        let dropped = stats.dropped();
+        retry_queue.abandon();
+        assert!(retry_queue.is_empty());
+        assert_eq!(stats.dropped(), dropped + 2);
+    }
+
+    #[test]
+    fn queues_nothing_without_capacity() {
+        let stats = Arc::new(RetryStats::default());
+        let mut retry_queue = RetryQueue::new(
+            &RetryConfig {
+                capacity: 0,
+                ..RetryConfig::default()
+            },
+            stats.clone(),
+        );
+
+        retry_queue.push(tracked_data("A", State::Valid));
+
+        assert!(retry_queue.is_empty());
+        assert_eq!(stats.dropped(), 1);
+    }
+}
@@ -0,0 +1,144 @@
+//! HMAC-SHA256 signing of the serialized TrackedData, so that collectors can
+//! reject the records which were not sent by a tracker sharing their key.
+//!
+//! A signed payload is tagged, followed by the signature and the signed payload.
+//! Signing is the last step, so that payloads which were compressed or split into
+//! fragments get signed as they are sent, every fragment on its own.
+
+use crate::error::{Error, ErrorKind};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt;
+use std::sync::Arc;
+
+pub(crate) const SIGNED_TAG: u8 = 0x30;
+
+const SIGNATURE_SIZE: usize = 32;
+
+/// Tag and signature.
+pub(crate) const SIGNED_HEADER_SIZE: usize = 1 + SIGNATURE_SIZE;
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct SigningConfig {
+    /// File whose whole content is the key shared by the trackers and the collectors.
+    pub key_path: String,
+}
+
+impl SigningConfig {
+    pub fn new(key_path: impl Into<String>) -> SigningConfig {
+        SigningConfig {
+            key_path: key_path.into(),
+        }
+    }
+
+    pub fn load_key(&self) -> Result<SigningKey, Error> {
+        match std::fs::read(&self.key_path) {
+            Ok(key) if key.is_empty() => Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("signing key '{}' is empty", self.key_path),
+            )),
+            Ok(key) => Ok(SigningKey::new(key)),
+            Err(error) => Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("failed to read signing key '{}': {}", self.key_path, error),
+            )),
+        }
+    }
+}
+
+/// Why a payload was rejected by [`SigningKey::verify`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rejection {
+    Unsigned,
+    /// The signature does not match the payload, which was either modified or
+    /// signed with another key.
+    Tampered,
+}
+
+#[derive(Clone)]
+pub struct SigningKey {
+    key: Arc<[u8]>,
+}
+
+impl SigningKey {
+    pub fn new(key: impl Into<Vec<u8>>) -> SigningKey {
+        SigningKey {
+            key: key.into().into(),
+        }
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        // HMAC accepts keys of any length.
+        Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC should accept any key length")
+    }
+
+    pub(crate) fn sign(&self, payload: &[u8]) -> Vec<u8> {
+        let mut mac = self.mac();
+        mac.update(payload);
+
+        let mut signed = Vec::with_capacity(SIGNED_HEADER_SIZE + payload.len());
+        signed.push(SIGNED_TAG);
+        signed.extend_from_slice(&mac.finalize().into_bytes());
+        signed.extend_from_slice(payload);
+        signed
+    }
+
+    /// Checks the signature of the payload, returning the signed payload.
+    pub fn verify<'a>(&self, payload: &'a [u8]) -> Result<&'a [u8], Rejection> {
+        if payload.first() != Some(&SIGNED_TAG) || payload.len() < SIGNED_HEADER_SIZE {
+            return Err(Rejection::Unsigned);
+        }
+
+        let (signature, signed_payload) = payload[1..].split_at(SIGNATURE_SIZE);
+
+        let mut mac = self.mac();
+        mac.update(signed_payload);
+        match mac.verify_slice(signature) {
+            Ok(_) => Ok(signed_payload),
+            Err(_) => Err(Rejection::Tampered),
+        }
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SigningKey(..)")
+    }
+}
+
+/// Returns the signed payload without checking its signature, or the payload as is
+/// if it is not signed.
+pub(crate) fn strip_signature(payload: &[u8]) -> &[u8] {
+    if payload.first() == Some(&SIGNED_TAG) && payload.len() >= SIGNED_HEADER_SIZE {
+        &payload[SIGNED_HEADER_SIZE..]
+    } else {
+        payload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unsigned_and_tampered_payloads() {
+        let signing_key = SigningKey::new("key");
+        let payload = b"{\"id\":\"test_id\"}";
+
+        let signed = signing_key.sign(payload);
+        assert_eq!(signing_key.verify(&signed), Ok(&payload[..]));
+        assert_eq!(strip_signature(&signed), payload);
+
+        assert_eq!(signing_key.verify(payload), Err(Rejection::Unsigned));
+        assert_eq!(
+            SigningKey::new("other key").verify(&signed),
+            Err(Rejection::Tampered)
+        );
+
+        let mut tampered = signed.clone();
+        *tampered.last_mut().unwrap() = b']';
+        assert_eq!(signing_key.verify(&tampered), Err(Rejection::Tampered));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::output_sink::OutputSink;
+use crate::retry::RetryQueue;
+use crate::tracked_data::TrackedData;
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+
+/// Amount of TrackedData which can be waiting to be emitted by a sink
+/// before newer data starts being dropped.
+const SINK_QUEUE_CAPACITY: usize = 1024;
+
+/// Time given to a sink to emit its queued data and close before it is abandoned.
+const SINK_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Emits the TrackedData delivered to it through its own task, so that
+/// a slow or failing sink does not delay any other.
+pub(crate) struct SinkWorker {
+    sender: Sender<Arc<TrackedData>>,
+    join_handle: JoinHandle<()>,
+}
+
+impl SinkWorker {
+    /// # Arguments
+    /// * `sink` - Sink emitting the delivered data.
+    /// * `retry_queue` - Queue of the data whose emission failed, which is dropped if missing.
+    pub(crate) fn spawn(
+        mut sink: Box<dyn OutputSink>,
+        mut retry_queue: Option<RetryQueue>,
+    ) -> SinkWorker {
+        let (sender, mut receiver) =
+            tokio::sync::mpsc::channel::<Arc<TrackedData>>(SINK_QUEUE_CAPACITY);
+
+        let join_handle = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    received = receiver.recv() => match received {
+                        Some(tracked_data) => {
+                            emit(sink.as_mut(), retry_queue.as_mut(), tracked_data).await
+                        }
+                        None => break,
+                    },
+                    _ = next_retry(retry_queue.as_ref()) => {
+                        if let Some(retry_queue) = retry_queue.as_mut() {
+                            retry_queue.retry(sink.as_mut()).await;
+                        }
+                    }
+                    _ = sink.flush_due() => {
+                        if let Err(error) = sink.flush().await {
+                            log::error!("failed to flush output sink: {}", error);
+                        }
+                    }
+                }
+            }
+
+            if let Some(retry_queue) = retry_queue.as_mut() {
+                retry_queue.retry(sink.as_mut()).await;
+                retry_queue.abandon();
+            }
+
+            if let Err(error) = sink.close().await {
+                log::error!("failed to close output sink: {}", error);
+            }
+        });
+
+        SinkWorker {
+            sender,
+            join_handle,
+        }
+    }
+
+    /// Queues the data without waiting, dropping it if the sink is falling behind.
+    pub(crate) fn deliver(&self, tracked_data: Arc<TrackedData>) {
+        match self.sender.try_send(tracked_data) {
+            Ok(_) => (),
+            Err(TrySendError::Full(tracked_data)) => log::warn!(
+                "dropped tracked data of '{}' because the output sink is falling behind",
+                tracked_data.id
+            ),
+            Err(TrySendError::Closed(_)) => log::error!("output sink has stopped"),
+        }
+    }
+
+    /// Waits until the queued data has been emitted and the sink has been closed.
+    pub(crate) async fn stop(self) {
+        let mut join_handle = self.join_handle;
+        drop(self.sender);
+
+        match tokio::time::timeout(SINK_STOP_TIMEOUT, &mut join_handle).await {
+            Ok(Ok(_)) => (),
+            Ok(Err(error)) => log::error!("output sink failed while stopping: {}", error),
+            Err(_) => {
+                log::error!("output sink did not stop in time, abandoning it");
+                join_handle.abort();
+            }
+        }
+    }
+}
+
+async fn emit(
+    sink: &mut dyn OutputSink,
+    retry_queue: Option<&mut RetryQueue>,
+    tracked_data: Arc<TrackedData>,
+) {
+    match retry_queue {
+        // Preserving the order, the data waits for the pending data to be retried first.
+        Some(retry_queue) if !retry_queue.is_empty() => retry_queue.push(tracked_data),
+        retry_queue => {
+            if let Err(error) = sink.emit(&tracked_data).await {
+                log::error!("failed to output tracked data: {}", error);
+
+                if let Some(retry_queue) = retry_queue {
+                    retry_queue.push(tracked_data);
+                }
+            }
+        }
+    }
+}
+
+async fn next_retry(retry_queue: Option<&RetryQueue>) {
+    match retry_queue {
+        Some(retry_queue) => retry_queue.wait().await,
+        None => std::future::pending().await,
+    }
+}
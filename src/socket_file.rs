@@ -0,0 +1,314 @@
+//! Lifecycle of the files of the UnixDatagram sockets bound by the trackers.
+
+use crate::error;
+use crate::error::{Error, ErrorKind};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::net::UnixDatagram;
+
+/// How the file of a bound socket is handled. Every option is disabled by default.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Default, Debug)]
+pub struct SocketFileConfig {
+    /// Whether a socket file left by a process which stopped without removing it
+    /// gets removed before binding. Files on which a socket is still bound are kept.
+    #[serde(default)]
+    pub remove_stale: bool,
+    #[serde(default)]
+    pub create_parent_directories: bool,
+    /// Permissions of the socket file, as an octal string such as `"660"`. Numbers
+    /// are rejected, since they would be read as decimal ones.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_mode",
+        serialize_with = "serialize_mode",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub mode: Option<u32>,
+    /// Name of the group owning the socket file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Whether the socket file gets removed once the socket is no longer used,
+    /// e.g. when the StateTracker stops.
+    #[serde(default)]
+    pub remove_on_stop: bool,
+}
+
+/// File of a bound socket, which gets removed when dropped until its setup is
+/// complete, and then only if it was configured so.
+#[derive(Debug)]
+pub struct SocketFile {
+    path: PathBuf,
+    remove_on_drop: bool,
+    remove_on_stop: bool,
+}
+
+impl SocketFile {
+    /// Marks the setup of whatever uses the socket as complete, from which the file
+    /// is only removed when dropped if it was configured so.
+    pub fn complete_setup(&mut self) {
+        self.remove_on_drop = self.remove_on_stop;
+    }
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if !self.remove_on_drop {
+            return;
+        }
+
+        match std::fs::remove_file(&self.path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => log::warn!(
+                "failed to remove socket file '{}': {}",
+                self.path.display(),
+                error
+            ),
+            _ => (),
+        }
+    }
+}
+
+/// Binds an UnixDatagram socket to the `path`, handling its file as configured.
+///
+/// The file gets removed if the returned [`SocketFile`] is dropped before
+/// [`SocketFile::complete_setup`] is called, so that a failed setup leaves nothing behind.
+pub fn bind(path: &str, config: &SocketFileConfig) -> Result<(UnixDatagram, SocketFile), Error> {
+    if config.create_parent_directories {
+        if let Some(parent) = Path::new(path).parent() {
+            if let Err(error) = std::fs::create_dir_all(parent) {
+                return Err(Error::new(
+                    ErrorKind::ParentDirectoryMissing,
+                    format!("failed to create '{}': {}", parent.display(), error),
+                ));
+            }
+        }
+    }
+
+    if config.remove_stale {
+        remove_stale(path)?;
+    }
+
+    let socket = match UnixDatagram::bind(path) {
+        Ok(socket) => socket,
+        Err(error) => return Err(error::bind_error(path, error)),
+    };
+    let socket_file = SocketFile {
+        path: PathBuf::from(path),
+        remove_on_drop: true,
+        remove_on_stop: config.remove_on_stop,
+    };
+
+    if let Some(mode) = config.mode {
+        if let Err(error) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("failed to set the mode of '{}': {}", path, error),
+            ));
+        }
+    }
+
+    if let Some(group) = &config.group {
+        set_group(path, group)?;
+    }
+
+    Ok((socket, socket_file))
+}
+
+/// Removes the socket file at `path` unless a socket is still bound to it.
+fn remove_stale(path: &str) -> Result<(), Error> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => {
+            return Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("failed to inspect '{}': {}", path, error),
+            ))
+        }
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(Error::new(
+            ErrorKind::SocketPathInUse,
+            format!("'{}' exists and is not a socket", path),
+        ));
+    }
+
+    // Only a refused connection tells that no socket is bound to the file anymore.
+    match std::os::unix::net::UnixDatagram::unbound().and_then(|probe| probe.connect(path)) {
+        Ok(_) => {
+            return Err(Error::new(
+                ErrorKind::SocketPathInUse,
+                format!("a socket is still bound to '{}'", path),
+            ))
+        }
+        Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => (),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => {
+            return Err(Error::new(
+                ErrorKind::SocketPathInUse,
+                format!("failed to tell whether '{}' is stale: {}", path, error),
+            ))
+        }
+    }
+
+    log::info!("removing stale socket file '{}'", path);
+    match std::fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(Error::new(
+            ErrorKind::InternalFailure,
+            format!("failed to remove stale socket file '{}': {}", path, error),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn set_group(path: &str, group: &str) -> Result<(), Error> {
+    let gid = match nix::unistd::Group::from_name(group) {
+        Ok(Some(group)) => group.gid,
+        Ok(None) => {
+            return Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("group '{}' does not exist", group),
+            ))
+        }
+        Err(error) => {
+            return Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("failed to look up group '{}': {}", group, error),
+            ))
+        }
+    };
+
+    match nix::unistd::chown(path, None, Some(gid)) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("failed to set the group of '{}': {}", path, error),
+        )),
+    }
+}
+
+/// Largest mode, with the setuid, setgid and sticky bits set.
+const MAX_MODE: u32 = 0o7777;
+
+fn deserialize_mode<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(mode) => match u32::from_str_radix(&mode, 8) {
+            Ok(parsed_mode) if parsed_mode <= MAX_MODE => Ok(Some(parsed_mode)),
+            _ => Err(serde::de::Error::custom(format!(
+                "invalid octal mode '{}'",
+                mode
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn serialize_mode<S: Serializer>(mode: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error> {
+    match mode {
+        Some(mode) => serializer.collect_str(&format_args!("{:o}", mode)),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn removes_only_stale_sockets() {
+        const SOCKET_PATH: &str = "/tmp/cooplan_socket_file_test_stale.sock";
+
+        let _ = tokio::fs::remove_file(SOCKET_PATH).await;
+
+        let config = SocketFileConfig {
+            remove_stale: true,
+            ..SocketFileConfig::default()
+        };
+
+        let (socket, mut socket_file) = bind(SOCKET_PATH, &config).unwrap();
+        socket_file.complete_setup();
+        assert_eq!(
+            bind(SOCKET_PATH, &config).unwrap_err().kind(),
+            ErrorKind::SocketPathInUse
+        );
+
+        // Without removal, the file is left behind as if the process had crashed.
+        drop(socket);
+        drop(socket_file);
+        assert!(Path::new(SOCKET_PATH).exists());
+
+        bind(SOCKET_PATH, &config).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_sockets_which_cannot_be_probed() {
+        const SOCKET_PATH: &str = "/tmp/cooplan_socket_file_test_stream.sock";
+
+        let _ = tokio::fs::remove_file(SOCKET_PATH).await;
+
+        // Probing a stream socket fails with EPROTOTYPE rather than ECONNREFUSED.
+        let _listener = std::os::unix::net::UnixListener::bind(SOCKET_PATH).unwrap();
+
+        let config = SocketFileConfig {
+            remove_stale: true,
+            ..SocketFileConfig::default()
+        };
+        assert_eq!(
+            bind(SOCKET_PATH, &config).unwrap_err().kind(),
+            ErrorKind::SocketPathInUse
+        );
+        assert!(Path::new(SOCKET_PATH).exists());
+
+        let _ = std::fs::remove_file(SOCKET_PATH);
+    }
+
+    #[tokio::test]
+    async fn removes_socket_file_when_setup_fails() {
+        const SOCKET_PATH: &str = "/tmp/cooplan_socket_file_test_setup.sock";
+
+        let _ = tokio::fs::remove_file(SOCKET_PATH).await;
+
+        let config = SocketFileConfig {
+            group: Some("cooplan_missing_group".to_string()),
+            ..SocketFileConfig::default()
+        };
+        assert!(bind(SOCKET_PATH, &config).is_err());
+        assert!(!Path::new(SOCKET_PATH).exists());
+    }
+
+    #[test]
+    fn accepts_only_octal_modes() {
+        let mode =
+            |json: &str| serde_json::from_str::<SocketFileConfig>(json).map(|config| config.mode);
+
+        assert_eq!(mode(r#"{"mode": "4755"}"#).unwrap(), Some(0o4755));
+        assert!(mode(r#"{"mode": 660}"#).is_err());
+        assert!(mode(r#"{"mode": "10000"}"#).is_err());
+        assert!(mode(r#"{"mode": "9"}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn prepares_and_removes_socket_file() {
+        const DIRECTORY: &str = "/tmp/cooplan_socket_file_test";
+        const SOCKET_PATH: &str = "/tmp/cooplan_socket_file_test/nested/sender.sock";
+
+        let _ = tokio::fs::remove_dir_all(DIRECTORY).await;
+
+        let config: SocketFileConfig = serde_json::from_str(
+            r#"{"create_parent_directories": true, "mode": "600", "remove_on_stop": true}"#,
+        )
+        .unwrap();
+
+        let (_socket, mut socket_file) = bind(SOCKET_PATH, &config).unwrap();
+        socket_file.complete_setup();
+
+        let metadata = std::fs::metadata(SOCKET_PATH).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+
+        drop(socket_file);
+        assert!(!Path::new(SOCKET_PATH).exists());
+    }
+}
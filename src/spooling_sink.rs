@@ -0,0 +1,470 @@
+use crate::error::{Error, ErrorKind};
+use crate::output_sink::OutputSink;
+use crate::tracked_data::TrackedData;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::time::Instant;
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct SpoolConfig {
+    /// File in which the undelivered data is stored as JSON lines.
+    pub path: String,
+    /// Size of the file from which the oldest data gets dropped.
+    #[serde(default = "default_max_size_in_bytes")]
+    pub max_size_in_bytes: u64,
+    /// Interval at which the replay of the stored data is attempted, besides
+    /// on every emission.
+    #[serde(default = "default_replay_interval_in_seconds")]
+    pub replay_interval_in_seconds: u64,
+}
+
+fn default_max_size_in_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_replay_interval_in_seconds() -> u64 {
+    5
+}
+
+impl SpoolConfig {
+    pub fn new(path: impl Into<String>) -> SpoolConfig {
+        SpoolConfig {
+            path: path.into(),
+            max_size_in_bytes: default_max_size_in_bytes(),
+            replay_interval_in_seconds: default_replay_interval_in_seconds(),
+        }
+    }
+}
+
+/// Stores on disk the data which its sink fails to emit, replaying it in order
+/// on the following emissions, or periodically when flushed through
+/// [`OutputSink::flush_due`], once the sink works again.
+///
+/// Stored data which fails to be emitted while the data behind it is emitted is dropped,
+/// so that it cannot block the replay forever. Heartbeats are never stored, since they
+/// are outdated once replayed.
+pub struct SpoolingSink<S> {
+    sink: S,
+    config: SpoolConfig,
+    // Mirrors the content of the spool file.
+    spooled_lines: VecDeque<Vec<u8>>,
+    spooled_size: u64,
+    next_replay: Instant,
+}
+
+impl<S: OutputSink> SpoolingSink<S> {
+    /// Tries to create an instance of SpoolingSink, loading the data which was
+    /// left in the spool file by a previous instance.
+    pub fn try_new(sink: S, config: SpoolConfig) -> Result<Self, Error> {
+        let content = match std::fs::read(&config.path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => {
+                return Err(Error::new(
+                    ErrorKind::InternalFailure,
+                    format!("failed to read spool '{}': {}", config.path, error),
+                ))
+            }
+        };
+
+        let spooled_lines: VecDeque<Vec<u8>> = content
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_vec())
+            .collect();
+        let spooled_size = spooled_lines.iter().map(|line| line_size(line)).sum();
+
+        Ok(Self {
+            sink,
+            config,
+            spooled_lines,
+            spooled_size,
+            next_replay: Instant::now(),
+        })
+    }
+
+    /// Amount of data waiting to be replayed.
+    pub fn spooled(&self) -> usize {
+        self.spooled_lines.len()
+    }
+
+    async fn replay(&mut self) -> io::Result<()> {
+        self.next_replay =
+            Instant::now() + Duration::from_secs(self.config.replay_interval_in_seconds);
+
+        let spooled = self.spooled_lines.len();
+
+        while let Some(line) = self.spooled_lines.front() {
+            if let Err(error) = emit_line(&mut self.sink, line).await {
+                match self.spooled_lines.get(1) {
+                    // The sink works, so the data can never be emitted.
+                    Some(next_line) if emit_line(&mut self.sink, next_line).await.is_ok() => {
+                        log::error!(
+                            "dropped spooled data which failed to be replayed: {}",
+                            error
+                        );
+                        self.pop_front();
+                    }
+                    _ => break,
+                }
+            }
+
+            self.pop_front();
+        }
+
+        if self.spooled_lines.len() < spooled {
+            log::info!(
+                "replayed {} spooled records",
+                spooled - self.spooled_lines.len()
+            );
+            self.rewrite().await?;
+        }
+
+        Ok(())
+    }
+
+    fn pop_front(&mut self) {
+        if let Some(line) = self.spooled_lines.pop_front() {
+            self.spooled_size -= line_size(&line);
+        }
+    }
+
+    async fn spool(&mut self, line: Vec<u8>) -> io::Result<()> {
+        let size = line_size(&line);
+        if size > self.config.max_size_in_bytes {
+            log::error!("dropped data larger than the spool");
+            return Ok(());
+        }
+
+        let mut dropped = 0;
+        while self.spooled_size + size > self.config.max_size_in_bytes {
+            match self.spooled_lines.pop_front() {
+                Some(dropped_line) => {
+                    self.spooled_size -= line_size(&dropped_line);
+                    dropped += 1;
+                }
+                None => break,
+            }
+        }
+
+        let content = with_newline(&line);
+        self.spooled_size += size;
+        self.spooled_lines.push_back(line);
+
+        if dropped > 0 {
+            log::warn!("dropped the {} oldest spooled records", dropped);
+            self.rewrite().await
+        } else {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.config.path)
+                .await?;
+
+            file.write_all(&content).await
+        }
+    }
+
+    async fn rewrite(&mut self) -> io::Result<()> {
+        if self.spooled_lines.is_empty() {
+            return match tokio::fs::remove_file(&self.config.path).await {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+                _ => Ok(()),
+            };
+        }
+
+        let temporary_path = format!("{}.tmp", self.config.path);
+        let mut content = Vec::with_capacity(self.spooled_size as usize);
+        for line in self.spooled_lines.iter() {
+            content.extend_from_slice(line);
+            content.push(b'\n');
+        }
+
+        tokio::fs::write(&temporary_path, content).await?;
+        tokio::fs::rename(&temporary_path, &self.config.path).await
+    }
+
+    fn spool_error(&self, error: io::Error) -> Error {
+        Error::new(
+            ErrorKind::InternalFailure,
+            format!("failed to update spool '{}': {}", self.config.path, error),
+        )
+    }
+}
+
+/// Emits a spooled line, dropping it with success if it is malformed.
+async fn emit_line(sink: &mut impl OutputSink, line: &[u8]) -> Result<(), Error> {
+    match serde_json::from_slice::<TrackedData>(line) {
+        Ok(tracked_data) => sink.emit(&tracked_data).await,
+        Err(error) => {
+            log::error!("dropped malformed spooled data: {}", error);
+            Ok(())
+        }
+    }
+}
+
+fn line_size(line: &[u8]) -> u64 {
+    line.len() as u64 + 1
+}
+
+fn with_newline(line: &[u8]) -> Vec<u8> {
+    let mut content = Vec::with_capacity(line.len() + 1);
+    content.extend_from_slice(line);
+    content.push(b'\n');
+    content
+}
+
+#[async_trait]
+impl<S: OutputSink> OutputSink for SpoolingSink<S> {
+    async fn emit(&mut self, tracked_data: &TrackedData) -> Result<(), Error> {
+        if let Err(error) = self.replay().await {
+            return Err(self.spool_error(error));
+        }
+
+        if self.spooled_lines.is_empty() {
+            match self.sink.emit(tracked_data).await {
+                Ok(_) => return Ok(()),
+                Err(error) => log::warn!("spooling undelivered data: {}", error),
+            }
+        }
+
+        if tracked_data.heartbeat {
+            return Ok(());
+        }
+
+        let line = tracked_data.to_json()?;
+        match self.spool(line).await {
+            Ok(_) => Ok(()),
+            Err(error) => Err(self.spool_error(error)),
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        if let Err(error) = self.replay().await {
+            return Err(self.spool_error(error));
+        }
+
+        self.sink.flush().await
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        self.sink.close().await
+    }
+
+    async fn flush_due(&mut self) {
+        if self.spooled_lines.is_empty() {
+            std::future::pending::<()>().await;
+        }
+
+        tokio::time::sleep_until(self.next_replay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink_worker::SinkWorker;
+    use crate::state::State;
+    use crate::unix_datagram_sink::UnixDatagramSink;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+    use tokio::net::UnixDatagram;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn replays_in_order_once_receiver_is_available() {
+        const RECEIVER_PATH: &str = "/tmp/cooplan_spooling_sink_test_receiver.sock";
+        const SPOOL_PATH: &str = "/tmp/cooplan_spooling_sink_test.spool";
+
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+        let _ = tokio::fs::remove_file(SPOOL_PATH).await;
+
+        let mut sink = SpoolingSink::try_new(
+            UnixDatagramSink::new(Arc::new(UnixDatagram::unbound().unwrap()), RECEIVER_PATH),
+            SpoolConfig::new(SPOOL_PATH),
+        )
+        .unwrap();
+
+        for id in 0..3 {
+            sink.emit(&TrackedData::new(
+                id.to_string(),
+                State::Valid,
+                SystemTime::now(),
+            ))
+            .await
+            .unwrap();
+        }
+        assert_eq!(sink.spooled(), 3);
+
+        let output_receiver = UnixDatagram::bind(RECEIVER_PATH).unwrap();
+
+        sink.emit(&TrackedData::new(
+            "3".to_string(),
+            State::Valid,
+            SystemTime::now(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(sink.spooled(), 0);
+        assert!(!tokio::fs::try_exists(SPOOL_PATH).await.unwrap());
+
+        let mut buffer = [0; 1024];
+        for id in 0..4 {
+            let length = output_receiver.recv(&mut buffer).await.unwrap();
+            let tracked_data = serde_json::from_slice::<TrackedData>(&buffer[..length]).unwrap();
+
+            assert_eq!(tracked_data.id, id.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn drops_oldest_data_and_survives_restarts() {
+        const RECEIVER_PATH: &str = "/tmp/cooplan_spooling_sink_test_missing_receiver.sock";
+        const SPOOL_PATH: &str = "/tmp/cooplan_spooling_sink_test_bounded.spool";
+
+        let _ = tokio::fs::remove_file(SPOOL_PATH).await;
+
+        let record_size = line_size(
+            &TrackedData::new("0".to_string(), State::Valid, SystemTime::now())
+                .to_json()
+                .unwrap(),
+        );
+        let spool_config = SpoolConfig {
+            max_size_in_bytes: record_size * 2,
+            ..SpoolConfig::new(SPOOL_PATH)
+        };
+
+        let mut sink = SpoolingSink::try_new(
+            UnixDatagramSink::new(Arc::new(UnixDatagram::unbound().unwrap()), RECEIVER_PATH),
+            spool_config.clone(),
+        )
+        .unwrap();
+
+        for id in 0..3 {
+            sink.emit(&TrackedData::new(
+                id.to_string(),
+                State::Valid,
+                SystemTime::now(),
+            ))
+            .await
+            .unwrap();
+        }
+
+        let sink = SpoolingSink::try_new(
+            UnixDatagramSink::new(Arc::new(UnixDatagram::unbound().unwrap()), RECEIVER_PATH),
+            spool_config,
+        )
+        .unwrap();
+
+        let spooled_ids: Vec<String> = sink
+            .spooled_lines
+            .iter()
+            .map(|line| serde_json::from_slice::<TrackedData>(line).unwrap().id)
+            .collect();
+        assert_eq!(spooled_ids, vec!["1", "2"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replays_periodically_without_emissions() {
+        const RECEIVER_PATH: &str = "/tmp/cooplan_spooling_sink_test_periodic_receiver.sock";
+        const SPOOL_PATH: &str = "/tmp/cooplan_spooling_sink_test_periodic.spool";
+
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+        let _ = tokio::fs::remove_file(SPOOL_PATH).await;
+
+        let mut sink = SpoolingSink::try_new(
+            UnixDatagramSink::new(Arc::new(UnixDatagram::unbound().unwrap()), RECEIVER_PATH),
+            SpoolConfig::new(SPOOL_PATH),
+        )
+        .unwrap();
+
+        sink.emit(&TrackedData::new(
+            "0".to_string(),
+            State::Valid,
+            SystemTime::now(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(sink.spooled(), 1);
+
+        let output_receiver = UnixDatagram::bind(RECEIVER_PATH).unwrap();
+        let sink_worker = SinkWorker::spawn(Box::new(sink), None);
+
+        let mut buffer = [0; 1024];
+        let length = timeout(Duration::from_secs(60), output_receiver.recv(&mut buffer))
+            .await
+            .expect("spooled data should have been replayed")
+            .unwrap();
+        let tracked_data = serde_json::from_slice::<TrackedData>(&buffer[..length]).unwrap();
+        assert_eq!(tracked_data.id, "0");
+
+        sink_worker.stop().await;
+    }
+
+    /// Fails while unavailable, and always for the data of the [`POISONED_ID`].
+    struct FlakySink {
+        available: Arc<AtomicBool>,
+        emitted_ids: Arc<Mutex<Vec<String>>>,
+    }
+
+    const POISONED_ID: &str = "poisoned";
+
+    #[async_trait]
+    impl OutputSink for FlakySink {
+        async fn emit(&mut self, tracked_data: &TrackedData) -> Result<(), Error> {
+            if !self.available.load(Ordering::Relaxed) || tracked_data.id == POISONED_ID {
+                return Err(Error::new(ErrorKind::InternalFailure, "unavailable"));
+            }
+
+            self.emitted_ids
+                .lock()
+                .unwrap()
+                .push(tracked_data.id.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn drops_data_blocking_the_replay() {
+        const SPOOL_PATH: &str = "/tmp/cooplan_spooling_sink_test_poisoned.spool";
+
+        let _ = tokio::fs::remove_file(SPOOL_PATH).await;
+
+        let available = Arc::new(AtomicBool::new(false));
+        let emitted_ids = Arc::new(Mutex::new(Vec::new()));
+        let mut sink = SpoolingSink::try_new(
+            FlakySink {
+                available: available.clone(),
+                emitted_ids: emitted_ids.clone(),
+            },
+            SpoolConfig::new(SPOOL_PATH),
+        )
+        .unwrap();
+
+        for id in [POISONED_ID, "1", "2"] {
+            sink.emit(&TrackedData::new(
+                id.to_string(),
+                State::Valid,
+                SystemTime::now(),
+            ))
+            .await
+            .unwrap();
+        }
+
+        // Nothing gets dropped while the sink is failing for every data.
+        sink.flush().await.unwrap();
+        assert_eq!(sink.spooled(), 3);
+
+        available.store(true, Ordering::Relaxed);
+        sink.flush().await.unwrap();
+
+        assert_eq!(sink.spooled(), 0);
+        assert_eq!(*emitted_ids.lock().unwrap(), vec!["1", "2"]);
+    }
+}
@@ -1,14 +1,107 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
 pub enum State {
     Idle,
     Valid,
     Error(String),
+    /// Functioning with reduced capabilities, e.g. during a partial outage.
+    Degraded(String),
+    Starting,
+    Stopping,
+    /// The state could not be determined.
+    Unknown,
+}
+
+/// How concerning a state is, ordered from least to most severe.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Severity {
+    Healthy,
+    Transitioning,
+    Unknown,
+    Degraded,
+    Error,
+}
+
+/// Version of the serialized states, allowing receivers which only understand
+/// the original `Idle`, `Valid` and `Error` states to keep working.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum WireVersion {
+    /// Only `Idle`, `Valid` and `Error` states are serialized.
+    #[default]
+    V1,
+    /// Every state is serialized as is.
+    V2,
 }
 
 impl State {
     pub fn is_error(&self) -> bool {
         matches!(self, State::Error(_))
     }
+
+    pub fn is_healthy(&self) -> bool {
+        self.severity() == Severity::Healthy
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            State::Idle | State::Valid => Severity::Healthy,
+            State::Starting | State::Stopping => Severity::Transitioning,
+            State::Unknown => Severity::Unknown,
+            State::Degraded(_) => Severity::Degraded,
+            State::Error(_) => Severity::Error,
+        }
+    }
+
+    /// Whether the state must be reported without any throttling.
+    pub fn is_urgent(&self) -> bool {
+        self.severity() >= Severity::Degraded
+    }
+
+    /// Converts the state into its closest equivalent understood by `wire_version` receivers.
+    ///
+    /// [`WireVersion::V1`] receivers get degraded components as valid, since they are still
+    /// functioning. [`crate::tracked_data::TrackedData::to_wire_version`] keeps the message.
+    pub fn to_wire_version(&self, wire_version: WireVersion) -> State {
+        match wire_version {
+            WireVersion::V1 => match self {
+                State::Degraded(_) => State::Valid,
+                State::Starting | State::Stopping | State::Unknown => State::Idle,
+                state => state.clone(),
+            },
+            WireVersion::V2 => self.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn severity_is_ordered() {
+        assert!(State::Valid.severity() < State::Starting.severity());
+        assert!(State::Starting.severity() < State::Unknown.severity());
+        assert!(State::Unknown.severity() < State::Degraded(String::new()).severity());
+        assert!(State::Degraded(String::new()).severity() < State::Error(String::new()).severity());
+    }
+
+    #[test]
+    fn v1_only_contains_original_states() {
+        const MESSAGE: &str = "MESSAGE";
+
+        assert_eq!(
+            State::Degraded(MESSAGE.to_string()).to_wire_version(WireVersion::V1),
+            State::Valid
+        );
+        assert_eq!(
+            State::Stopping.to_wire_version(WireVersion::V1),
+            State::Idle
+        );
+        assert_eq!(State::Valid.to_wire_version(WireVersion::V1), State::Valid);
+        assert_eq!(
+            State::Stopping.to_wire_version(WireVersion::V2),
+            State::Stopping
+        );
+    }
 }
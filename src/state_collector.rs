@@ -0,0 +1,567 @@
+use crate::encoding;
+use crate::envelope::{Continuity, SequenceTracker, SCHEMA_VERSION};
+use crate::error;
+use crate::error::{Error, ErrorKind};
+use crate::oversize::Reassembler;
+use crate::peer_credentials;
+use crate::signing;
+use crate::signing::{Rejection, SigningKey};
+use crate::state_collector_config::StateCollectorConfig;
+use crate::tracked_data::TrackedData;
+
+use futures::Stream;
+use std::collections::HashSet;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::net::UnixDatagram;
+
+/// Largest payload which can be received through an UnixDatagram socket,
+/// larger ones being skipped.
+pub const MAX_DATAGRAM_SIZE: usize = 65536;
+
+/// Receives the outputs of a StateTracker through an UnixDatagram socket
+/// and decodes them back into TrackedData objects, whichever their encoding.
+pub struct StateCollector {
+    socket: UnixDatagram,
+    buffer: Vec<u8>,
+    sequence_tracker: SequenceTracker,
+    reassembler: Reassembler,
+    verification_key: Option<SigningKey>,
+    stats: Arc<CollectorStats>,
+    allowed_uids: Option<HashSet<u32>>,
+}
+
+/// Counters of the payloads rejected by a StateCollector.
+#[derive(Default, Debug)]
+pub struct CollectorStats {
+    unsigned: AtomicU64,
+    tampered: AtomicU64,
+    forbidden: AtomicU64,
+}
+
+impl CollectorStats {
+    /// Payloads which were not signed, see [`StateCollector::with_verification_key`].
+    pub fn unsigned(&self) -> u64 {
+        self.unsigned.load(Ordering::Relaxed)
+    }
+
+    /// Payloads whose signature does not match the verification key.
+    pub fn tampered(&self) -> u64 {
+        self.tampered.load(Ordering::Relaxed)
+    }
+
+    /// Payloads sent by processes whose uid is not allowed,
+    /// see [`StateCollector::with_allowed_uids`].
+    pub fn forbidden(&self) -> u64 {
+        self.forbidden.load(Ordering::Relaxed)
+    }
+
+    fn count_rejection(&self, rejection: Rejection) {
+        let counter = match rejection {
+            Rejection::Unsigned => &self.unsigned,
+            Rejection::Tampered => &self.tampered,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl StateCollector {
+    /// Tries to create an instance of StateCollector.
+    ///
+    /// # Arguments
+    /// * `receiver_path` - Path to the UnixDatagram socket that will receive the outputs.
+    pub fn try_new(receiver_path: &str) -> Result<Self, Error> {
+        let socket = match UnixDatagram::bind(receiver_path) {
+            Ok(socket) => socket,
+            Err(error) => return Err(error::bind_error(receiver_path, error)),
+        };
+
+        if let Err(error) = peer_credentials::enable(&socket) {
+            return Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("failed to enable peer credentials: {}", error),
+            ));
+        }
+
+        Ok(Self {
+            socket,
+            buffer: vec![0; MAX_DATAGRAM_SIZE],
+            sequence_tracker: SequenceTracker::new(),
+            reassembler: Reassembler::new(),
+            verification_key: None,
+            stats: Arc::new(CollectorStats::default()),
+            allowed_uids: None,
+        })
+    }
+
+    /// Tries to create an instance of StateCollector, verifying the payloads and
+    /// their senders as configured.
+    pub fn try_from_config(state_collector_config: &StateCollectorConfig) -> Result<Self, Error> {
+        let mut state_collector =
+            StateCollector::try_new(&state_collector_config.state_output_receiver_path)?;
+
+        if let Some(allowed_uids) = &state_collector_config.state_allowed_uids {
+            state_collector = state_collector.with_allowed_uids(allowed_uids.iter().copied());
+        }
+
+        if let Some(signing_config) = &state_collector_config.state_signing {
+            state_collector = state_collector.with_verification_key(signing_config.load_key()?);
+        }
+
+        Ok(state_collector)
+    }
+
+    /// Rejects the payloads sent by processes whose uid is not one of the `allowed_uids`,
+    /// counting them in the [`StateCollector::stats`].
+    ///
+    /// Every payload gets rejected where the kernel does not report the credentials
+    /// of the senders, which is anywhere but on Linux.
+    pub fn with_allowed_uids(mut self, allowed_uids: impl IntoIterator<Item = u32>) -> Self {
+        self.allowed_uids = Some(allowed_uids.into_iter().collect());
+        self
+    }
+
+    /// Rejects the payloads which are not signed with the key, counting them in the
+    /// [`StateCollector::stats`].
+    pub fn with_verification_key(mut self, verification_key: SigningKey) -> Self {
+        self.verification_key = Some(verification_key);
+        self
+    }
+
+    pub fn stats(&self) -> Arc<CollectorStats> {
+        self.stats.clone()
+    }
+
+    /// Waits for the next TrackedData, logging and skipping malformed payloads
+    /// as well as those of encodings which are not enabled. Fragmented payloads are
+    /// reassembled once every fragment has been received.
+    ///
+    /// Data which was already received from the same tracker instance, according
+    /// to its [`crate::envelope::Envelope`], gets skipped as well, as does data of a newer
+    /// [`SCHEMA_VERSION`] than the supported one.
+    ///
+    /// The returned data carries the credentials of its sender when they are known.
+    pub async fn receive(&mut self) -> Result<TrackedData, Error> {
+        loop {
+            let (length, sender_credentials) =
+                match peer_credentials::recv(&self.socket, &mut self.buffer).await {
+                    Ok(received) => received,
+                    Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+                        log::error!("skipped datagram: {}", error);
+                        continue;
+                    }
+                    Err(error) => {
+                        return Err(Error::new(
+                            ErrorKind::InternalFailure,
+                            format!("failed to read from input socket: {}", error),
+                        ))
+                    }
+                };
+
+            if let Some(allowed_uids) = &self.allowed_uids {
+                match sender_credentials {
+                    Some(credentials) if allowed_uids.contains(&credentials.uid) => (),
+                    _ => {
+                        log::warn!("rejected payload from {:?}", sender_credentials);
+                        self.stats.forbidden.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                }
+            }
+
+            // Fragments are signed one by one, so that forged ones are rejected
+            // before being reassembled.
+            let datagram = match &self.verification_key {
+                Some(verification_key) => match verification_key.verify(&self.buffer[..length]) {
+                    Ok(datagram) => datagram,
+                    Err(rejection) => {
+                        log::warn!("rejected payload: {:?}", rejection);
+                        self.stats.count_rejection(rejection);
+                        continue;
+                    }
+                },
+                None => signing::strip_signature(&self.buffer[..length]),
+            };
+
+            let payload = match self.reassembler.push(datagram) {
+                Ok(Some(payload)) => payload,
+                Ok(None) => continue,
+                Err(error) => {
+                    log::error!("failed to reassemble payload: {}", error);
+                    continue;
+                }
+            };
+
+            let mut tracked_data = match encoding::decode(&payload) {
+                Ok(tracked_data) => tracked_data,
+                Err(error) => {
+                    log::error!("{}", error);
+                    continue;
+                }
+            };
+
+            if let Some(envelope) = &tracked_data.envelope {
+                if envelope.schema_version > SCHEMA_VERSION {
+                    log::error!(
+                        "skipped record of tracker {} with unsupported schema version {}",
+                        envelope.instance_id,
+                        envelope.schema_version
+                    );
+                    continue;
+                }
+
+                match self.sequence_tracker.track(envelope) {
+                    Continuity::NewInstance => {
+                        log::info!("receiving from tracker {}", envelope.instance_id)
+                    }
+                    Continuity::Consecutive => {}
+                    Continuity::Gap { missed } => log::warn!(
+                        "lost {} records from tracker {}",
+                        missed,
+                        envelope.instance_id
+                    ),
+                    Continuity::Reordered => log::info!(
+                        "received record {} from tracker {} out of order",
+                        envelope.sequence,
+                        envelope.instance_id
+                    ),
+                    Continuity::Duplicate => {
+                        log::debug!("skipped duplicated record {}", envelope.sequence);
+                        continue;
+                    }
+                }
+            }
+
+            tracked_data.sender_credentials = sender_credentials;
+            return Ok(tracked_data);
+        }
+    }
+
+    /// Turns the collector into a stream of the received TrackedData,
+    /// which ends if the socket fails.
+    pub fn into_stream(self) -> impl Stream<Item = TrackedData> {
+        futures::stream::unfold(self, |mut collector| async move {
+            match collector.receive().await {
+                Ok(tracked_data) => Some((tracked_data, collector)),
+                Err(error) => {
+                    log::error!("stopped collecting states: {}", error);
+                    None
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::PayloadFormat;
+    use crate::envelope::Envelope;
+    use crate::output_sink::OutputSink;
+    use crate::oversize::{OversizeConfig, OversizePolicy};
+    use crate::state::State;
+    use crate::unix_datagram_sink::UnixDatagramSink;
+    use futures::StreamExt;
+    use std::time::{Duration, SystemTime};
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn skips_malformed_payloads() {
+        const RECEIVER_PATH: &str = "/tmp/cooplan_state_collector_test_receiver.sock";
+        const TEST_ID: &str = "test_id";
+
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+
+        let state_collector = StateCollector::try_new(RECEIVER_PATH).unwrap();
+        let mut states = Box::pin(state_collector.into_stream());
+
+        let sender = UnixDatagram::unbound().unwrap();
+        let tracked_data = TrackedData::new(TEST_ID.to_string(), State::Valid, SystemTime::now());
+
+        sender.send_to(b"not json", RECEIVER_PATH).await.unwrap();
+        sender
+            .send_to(&serde_json::to_vec(&tracked_data).unwrap(), RECEIVER_PATH)
+            .await
+            .unwrap();
+
+        let received = timeout(Duration::from_secs(3), states.next())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(received.id, TEST_ID);
+        assert_eq!(received.state, State::Valid);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn skips_truncated_datagrams() {
+        const RECEIVER_PATH: &str = "/tmp/cooplan_state_collector_test_truncated_receiver.sock";
+
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+
+        let mut state_collector = StateCollector::try_new(RECEIVER_PATH).unwrap();
+        let sender = UnixDatagram::unbound().unwrap();
+
+        // Invalid as a whole, while its truncated part would be valid.
+        let mut truncated =
+            TrackedData::new("truncated".to_string(), State::Valid, SystemTime::now())
+                .to_json()
+                .unwrap();
+        truncated.resize(MAX_DATAGRAM_SIZE, b' ');
+        truncated.extend_from_slice(b"invalid");
+        sender.send_to(&truncated, RECEIVER_PATH).await.unwrap();
+
+        let tracked_data = TrackedData::new("test_id".to_string(), State::Valid, SystemTime::now());
+        sender
+            .send_to(&tracked_data.to_json().unwrap(), RECEIVER_PATH)
+            .await
+            .unwrap();
+
+        let received = timeout(Duration::from_secs(3), state_collector.receive())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(received.id, tracked_data.id);
+    }
+
+    #[tokio::test]
+    async fn skips_duplicated_and_unsupported_data() {
+        const RECEIVER_PATH: &str = "/tmp/cooplan_state_collector_test_duplicates_receiver.sock";
+
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+
+        let mut state_collector = StateCollector::try_new(RECEIVER_PATH).unwrap();
+
+        let sender = UnixDatagram::unbound().unwrap();
+        let instance_id = uuid::Uuid::new_v4();
+        for (id, sequence, schema_version) in [
+            ("0", 0, SCHEMA_VERSION),
+            ("2", 2, SCHEMA_VERSION),
+            ("1", 1, SCHEMA_VERSION),
+            ("duplicate", 1, SCHEMA_VERSION),
+            ("unsupported", 3, SCHEMA_VERSION + 1),
+            ("3", 3, SCHEMA_VERSION),
+        ] {
+            let mut tracked_data =
+                TrackedData::new(id.to_string(), State::Valid, SystemTime::now());
+            tracked_data.envelope = Some(Envelope {
+                schema_version,
+                instance_id,
+                booted_at: SystemTime::now(),
+                sequence,
+            });
+
+            sender
+                .send_to(&tracked_data.to_json().unwrap(), RECEIVER_PATH)
+                .await
+                .unwrap();
+        }
+
+        for id in ["0", "2", "1", "3"] {
+            let received = timeout(Duration::from_secs(3), state_collector.receive())
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(received.id, id);
+        }
+    }
+
+    #[tokio::test]
+    async fn reassembles_fragmented_payloads() {
+        const RECEIVER_PATH: &str = "/tmp/cooplan_state_collector_test_fragments_receiver.sock";
+
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+
+        let mut state_collector = StateCollector::try_new(RECEIVER_PATH).unwrap();
+
+        let mut sink =
+            UnixDatagramSink::new(Arc::new(UnixDatagram::unbound().unwrap()), RECEIVER_PATH)
+                .with_oversize_config(OversizeConfig {
+                    max_datagram_size_in_bytes: 256,
+                    policy: OversizePolicy::Fragment,
+                });
+        let state = State::Error("error".repeat(200));
+        sink.emit(&TrackedData::new(
+            "test_id".to_string(),
+            state.clone(),
+            SystemTime::now(),
+        ))
+        .await
+        .unwrap();
+
+        let received = timeout(Duration::from_secs(3), state_collector.receive())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(received.state, state);
+    }
+
+    #[tokio::test]
+    async fn rejects_unsigned_and_tampered_payloads() {
+        const RECEIVER_PATH: &str = "/tmp/cooplan_state_collector_test_signed_receiver.sock";
+
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+
+        let signing_key = SigningKey::new("key");
+        let mut state_collector = StateCollector::try_new(RECEIVER_PATH)
+            .unwrap()
+            .with_verification_key(signing_key.clone());
+        let stats = state_collector.stats();
+
+        let sender = UnixDatagram::unbound().unwrap();
+        let sign = |id: &str, signing_key: &SigningKey| {
+            PayloadFormat {
+                signing_key: Some(signing_key.clone()),
+                ..PayloadFormat::default()
+            }
+            .serialize(&TrackedData::new(
+                id.to_string(),
+                State::Valid,
+                SystemTime::now(),
+            ))
+            .unwrap()
+        };
+
+        let unsigned = TrackedData::new("unsigned".to_string(), State::Valid, SystemTime::now());
+        for payload in [
+            unsigned.to_json().unwrap(),
+            sign("tampered", &SigningKey::new("other key")),
+            sign("signed", &signing_key),
+        ] {
+            sender.send_to(&payload, RECEIVER_PATH).await.unwrap();
+        }
+
+        let received = timeout(Duration::from_secs(3), state_collector.receive())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(received.id, "signed");
+        assert_eq!(stats.unsigned(), 1);
+        assert_eq!(stats.tampered(), 1);
+    }
+
+    #[tokio::test]
+    async fn reads_configuration_of_trackers() {
+        const RECEIVER_PATH: &str = "/tmp/cooplan_state_collector_test_config_receiver.sock";
+
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+
+        let state_collector_config: StateCollectorConfig = serde_json::from_str(&format!(
+            r#"{{"state_output_receiver_path": "{}", "state_sender_interval_in_seconds": 1}}"#,
+            RECEIVER_PATH
+        ))
+        .unwrap();
+        let mut state_collector = StateCollector::try_from_config(&state_collector_config).unwrap();
+
+        let tracked_data = TrackedData::new("test_id".to_string(), State::Valid, SystemTime::now());
+        UnixDatagram::unbound()
+            .unwrap()
+            .send_to(&tracked_data.to_json().unwrap(), RECEIVER_PATH)
+            .await
+            .unwrap();
+
+        let received = timeout(Duration::from_secs(3), state_collector.receive())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(received.id, tracked_data.id);
+    }
+
+    #[tokio::test]
+    async fn verifies_oversized_payloads() {
+        const RECEIVER_PATH: &str =
+            "/tmp/cooplan_state_collector_test_signed_oversized_receiver.sock";
+
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+
+        let signing_key = SigningKey::new("key");
+        let mut state_collector = StateCollector::try_new(RECEIVER_PATH)
+            .unwrap()
+            .with_verification_key(signing_key.clone());
+        let stats = state_collector.stats();
+
+        let state = State::Error("error".repeat(200));
+        for policy in [OversizePolicy::Compress, OversizePolicy::Fragment] {
+            let mut sink =
+                UnixDatagramSink::new(Arc::new(UnixDatagram::unbound().unwrap()), RECEIVER_PATH)
+                    .with_payload_format(PayloadFormat {
+                        signing_key: Some(signing_key.clone()),
+                        ..PayloadFormat::default()
+                    })
+                    .with_oversize_config(OversizeConfig {
+                        max_datagram_size_in_bytes: 256,
+                        policy,
+                    });
+            sink.emit(&TrackedData::new(
+                "test_id".to_string(),
+                state.clone(),
+                SystemTime::now(),
+            ))
+            .await
+            .unwrap();
+
+            let received = timeout(Duration::from_secs(3), state_collector.receive())
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(received.state, state);
+        }
+
+        assert_eq!(stats.unsigned(), 0);
+        assert_eq!(stats.tampered(), 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn attaches_sender_credentials_and_enforces_allowed_uids() {
+        const ALLOWED_RECEIVER_PATH: &str =
+            "/tmp/cooplan_state_collector_test_allowed_uid_receiver.sock";
+        const FORBIDDEN_RECEIVER_PATH: &str =
+            "/tmp/cooplan_state_collector_test_forbidden_uid_receiver.sock";
+
+        let _ = tokio::fs::remove_file(ALLOWED_RECEIVER_PATH).await;
+        let _ = tokio::fs::remove_file(FORBIDDEN_RECEIVER_PATH).await;
+
+        let uid = nix::unistd::getuid().as_raw();
+        let mut allowed_collector = StateCollector::try_new(ALLOWED_RECEIVER_PATH)
+            .unwrap()
+            .with_allowed_uids([uid]);
+        let mut forbidden_collector = StateCollector::try_new(FORBIDDEN_RECEIVER_PATH)
+            .unwrap()
+            .with_allowed_uids([uid.wrapping_add(1)]);
+        let stats = forbidden_collector.stats();
+
+        let sender = UnixDatagram::unbound().unwrap();
+        let payload = TrackedData::new("test_id".to_string(), State::Valid, SystemTime::now())
+            .to_json()
+            .unwrap();
+        for receiver_path in [ALLOWED_RECEIVER_PATH, FORBIDDEN_RECEIVER_PATH] {
+            sender.send_to(&payload, receiver_path).await.unwrap();
+        }
+
+        let received = timeout(Duration::from_secs(3), allowed_collector.receive())
+            .await
+            .unwrap()
+            .unwrap();
+        let sender_credentials = received.sender_credentials.unwrap();
+
+        assert_eq!(sender_credentials.uid, uid);
+        assert_eq!(sender_credentials.pid as u32, std::process::id());
+
+        assert!(
+            timeout(Duration::from_millis(200), forbidden_collector.receive())
+                .await
+                .is_err()
+        );
+        assert_eq!(stats.forbidden(), 1);
+    }
+}
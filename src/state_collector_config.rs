@@ -0,0 +1,18 @@
+use crate::signing::SigningConfig;
+use serde::{Deserialize, Serialize};
+
+/// Configuration of a [`crate::state_collector::StateCollector`].
+///
+/// Its keys are named as those of [`crate::state_tracking_config::StateTrackingConfig`],
+/// so that the collector can read the configuration of the trackers it receives from.
+#[derive(Deserialize, Serialize, Default)]
+pub struct StateCollectorConfig {
+    /// Path to which the UnixDatagram socket receiving the states is bound.
+    pub state_output_receiver_path: String,
+    /// Verification of the states, which are accepted unsigned when missing.
+    #[serde(default)]
+    pub state_signing: Option<SigningConfig>,
+    /// Uids of the processes from which the states are accepted, any if missing.
+    #[serde(default)]
+    pub state_allowed_uids: Option<Vec<u32>>,
+}
@@ -0,0 +1,223 @@
+use crate::error_info::ErrorInfo;
+use crate::state::{Severity, State};
+use crate::tracked_data::TrackedData;
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+/// Latest known state of a component.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct ComponentState {
+    pub state: State,
+    /// Details about the error which led to the state, if any.
+    pub error_info: Option<ErrorInfo>,
+    /// Timestamp of the latest TrackedData received for the component.
+    pub updated_at: SystemTime,
+    /// Timestamp of the latest TrackedData which changed the component's state.
+    pub transitioned_at: SystemTime,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum Health {
+    Healthy,
+    /// Some component is degraded, but none is in an error state.
+    Degraded,
+    Unhealthy,
+}
+
+/// Aggregates received TrackedData into the latest state of every component.
+#[derive(Default)]
+pub struct StateRegistry {
+    components: BTreeMap<String, ComponentState>,
+}
+
+impl StateRegistry {
+    pub fn new() -> StateRegistry {
+        StateRegistry::default()
+    }
+
+    /// Registers the TrackedData, ignoring it if it is older than the latest
+    /// one received for the same component.
+    pub fn update(&mut self, tracked_data: TrackedData) {
+        match self.components.get_mut(&tracked_data.id) {
+            Some(component) => {
+                if tracked_data.timestamp < component.updated_at {
+                    return;
+                }
+
+                if component.state != tracked_data.state {
+                    component.state = tracked_data.state;
+                    component.transitioned_at = tracked_data.timestamp;
+                }
+
+                component.error_info = tracked_data.error_info;
+                component.updated_at = tracked_data.timestamp;
+            }
+            None => {
+                self.components.insert(
+                    tracked_data.id,
+                    ComponentState {
+                        state: tracked_data.state,
+                        error_info: tracked_data.error_info,
+                        updated_at: tracked_data.timestamp,
+                        transitioned_at: tracked_data.timestamp,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ComponentState> {
+        self.components.get(id)
+    }
+
+    pub fn components(&self) -> &BTreeMap<String, ComponentState> {
+        &self.components
+    }
+
+    /// Ids of the components whose latest state is an error.
+    pub fn in_error(&self) -> Vec<&str> {
+        self.components
+            .iter()
+            .filter(|(_, component)| component.state.is_error())
+            .map(|(id, _)| id.as_str())
+            .collect()
+    }
+
+    /// Ids of the components which have not been updated during the latest `duration`.
+    pub fn not_updated_for(&self, duration: Duration) -> Vec<&str> {
+        let now = SystemTime::now();
+
+        self.components
+            .iter()
+            .filter(
+                |(_, component)| match now.duration_since(component.updated_at) {
+                    Ok(elapsed) => elapsed >= duration,
+                    Err(_) => false,
+                },
+            )
+            .map(|(id, _)| id.as_str())
+            .collect()
+    }
+
+    /// Whether every one of the `ids` has been registered with the [`State::Valid`] state.
+    pub fn all_valid(&self, ids: &[String]) -> bool {
+        ids.iter().all(|id| match self.components.get(id) {
+            Some(component) => component.state == State::Valid,
+            None => false,
+        })
+    }
+
+    /// Whether some TrackedData has been registered and every one of the `required_ids`
+    /// is in the [`State::Valid`] state.
+    pub fn is_ready(&self, required_ids: &[String]) -> bool {
+        !self.components.is_empty() && self.all_valid(required_ids)
+    }
+
+    /// Unhealthy if any component is in an error state, degraded if any is degraded.
+    pub fn health(&self) -> Health {
+        match self
+            .components
+            .values()
+            .map(|component| component.state.severity())
+            .max()
+        {
+            Some(Severity::Error) => Health::Unhealthy,
+            Some(Severity::Degraded) => Health::Degraded,
+            _ => Health::Healthy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_transition_time_on_repeated_state() {
+        const ID: &str = "ID";
+
+        let transitioned_at = SystemTime::now() - Duration::from_secs(10);
+        let updated_at = SystemTime::now();
+
+        let mut state_registry = StateRegistry::new();
+        state_registry.update(TrackedData::new(
+            ID.to_string(),
+            State::Valid,
+            transitioned_at,
+        ));
+        state_registry.update(TrackedData::new(ID.to_string(), State::Valid, updated_at));
+
+        let component = state_registry.get(ID).unwrap();
+        assert_eq!(component.state, State::Valid);
+        assert_eq!(component.transitioned_at, transitioned_at);
+        assert_eq!(component.updated_at, updated_at);
+    }
+
+    #[test]
+    fn ignores_outdated_data() {
+        const ID: &str = "ID";
+
+        let mut state_registry = StateRegistry::new();
+        state_registry.update(TrackedData::new(
+            ID.to_string(),
+            State::Valid,
+            SystemTime::now(),
+        ));
+        state_registry.update(TrackedData::new(
+            ID.to_string(),
+            State::Idle,
+            SystemTime::now() - Duration::from_secs(10),
+        ));
+
+        assert_eq!(state_registry.get(ID).unwrap().state, State::Valid);
+    }
+
+    #[test]
+    fn answers_queries() {
+        const VALID_ID: &str = "VALID_ID";
+        const ERROR_ID: &str = "ERROR_ID";
+        const STALE_ID: &str = "STALE_ID";
+
+        let mut state_registry = StateRegistry::new();
+        assert_eq!(state_registry.health(), Health::Healthy);
+        assert!(!state_registry.is_ready(&[]));
+
+        state_registry.update(TrackedData::new(
+            ERROR_ID.to_string(),
+            State::Degraded("degraded".to_string()),
+            SystemTime::now() - Duration::from_secs(1),
+        ));
+        assert_eq!(state_registry.health(), Health::Degraded);
+
+        state_registry.update(TrackedData::new(
+            VALID_ID.to_string(),
+            State::Valid,
+            SystemTime::now(),
+        ));
+        state_registry.update(TrackedData::new(
+            ERROR_ID.to_string(),
+            State::Error("error".to_string()),
+            SystemTime::now(),
+        ));
+        state_registry.update(TrackedData::new(
+            STALE_ID.to_string(),
+            State::Idle,
+            SystemTime::now() - Duration::from_secs(60),
+        ));
+
+        assert_eq!(state_registry.in_error(), vec![ERROR_ID]);
+        assert_eq!(
+            state_registry.not_updated_for(Duration::from_secs(30)),
+            vec![STALE_ID]
+        );
+        assert_eq!(state_registry.health(), Health::Unhealthy);
+        assert!(state_registry.all_valid(&[VALID_ID.to_string()]));
+        assert!(!state_registry.all_valid(&[VALID_ID.to_string(), STALE_ID.to_string()]));
+        assert!(!state_registry.all_valid(&["UNKNOWN_ID".to_string()]));
+        assert!(state_registry.is_ready(&[]));
+        assert!(state_registry.is_ready(&[VALID_ID.to_string()]));
+        assert!(!state_registry.is_ready(&[ERROR_ID.to_string()]));
+    }
+}
@@ -1,18 +1,50 @@
-use crate::error::{Error, ErrorKind};
+use crate::envelope::{Envelope, SCHEMA_VERSION};
+use crate::error::Error;
+use crate::output_sink::OutputSink;
+use crate::retry::{RetryConfig, RetryQueue, RetryStats};
+use crate::sink_worker::SinkWorker;
+use crate::socket_file::SocketFile;
+use crate::state::{State, WireVersion};
+use crate::tracked_data;
 use crate::tracked_data::TrackedData;
+use crate::tracker_handle;
+use crate::tracker_handle::TrackerHandle;
+use crate::unix_datagram_sink::UnixDatagramSink;
 
-use tokio::net::UnixDatagram;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc::Receiver;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use uuid::Uuid;
+
+/// Id used by the tracker for the records it emits about itself.
+pub const TRACKER_ID: &str = "state_tracker";
 
 /// Receives state updates from functioning parts of any program
-/// and proceeds to output them through an UnixDatagram socket.
+/// and proceeds to output them through its output sinks.
+///
+/// Every sink emits concurrently from its own task, so that a slow or failing
+/// sink never delays the delivery to the others.
+///
+/// Every output is wrapped in an [`Envelope`] identifying the tracker instance
+/// and numbering the output, so that receivers can detect lost data and restarts.
 ///
 /// The purpose which it full-fills is to allow microservices to communicate
 /// the current state of all their functionalities easily through a standardized way.
 pub struct StateTracker {
     receiver: Receiver<TrackedData>,
-    output_sender: UnixDatagram,
-    output_receiver_path: String,
+    sinks: Vec<Box<dyn OutputSink>>,
+    heartbeat_interval: Option<Duration>,
+    wire_version: WireVersion,
+    retry_config: Option<RetryConfig>,
+    retry_stats: Arc<RetryStats>,
+    latest_tracked_data: HashMap<String, TrackedData>,
+    instance_id: Uuid,
+    booted_at: SystemTime,
+    next_sequence: u64,
+    socket_files: Vec<SocketFile>,
 }
 
 impl StateTracker {
@@ -27,55 +59,186 @@ impl StateTracker {
         output_receiver_path: &str,
         receiver: Receiver<TrackedData>,
     ) -> Result<Self, Error> {
-        let output_sender = match UnixDatagram::bind(output_sender_path) {
-            Ok(output) => output,
-            Err(error) => {
-                return Err(Error::new(
-                    ErrorKind::InternalFailure,
-                    format!("failed to bind to output path: {}", error),
-                ))
-            }
-        };
+        let sink = UnixDatagramSink::try_new(output_sender_path, output_receiver_path)?;
+
+        Ok(Self::new(receiver, vec![Box::new(sink)]))
+    }
 
-        Ok(Self {
+    /// Creates an instance of StateTracker outputting to every one of the `sinks`.
+    pub fn new(receiver: Receiver<TrackedData>, sinks: Vec<Box<dyn OutputSink>>) -> Self {
+        Self {
             receiver,
-            output_sender,
-            output_receiver_path: output_receiver_path.to_string(),
-        })
+            sinks,
+            heartbeat_interval: None,
+            wire_version: WireVersion::default(),
+            retry_config: None,
+            retry_stats: Arc::new(RetryStats::default()),
+            latest_tracked_data: HashMap::new(),
+            instance_id: Uuid::new_v4(),
+            booted_at: SystemTime::now(),
+            next_sequence: 0,
+            socket_files: Vec::new(),
+        }
+    }
+
+    /// Id identifying the outputs of this instance in their [`Envelope`].
+    pub fn instance_id(&self) -> Uuid {
+        self.instance_id
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn OutputSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Keeps the file of a socket used by the sinks until the tracker stops,
+    /// removing it then if it was configured so.
+    pub fn add_socket_file(&mut self, socket_file: SocketFile) {
+        self.socket_files.push(socket_file);
+    }
+
+    /// Sets the interval at which the latest data of every id gets outputted again,
+    /// marked as a heartbeat. Heartbeats are disabled with `None`, which is the default,
+    /// or with a zero interval.
+    pub fn set_heartbeat_interval(&mut self, heartbeat_interval: Option<Duration>) {
+        self.heartbeat_interval = heartbeat_interval.filter(|interval| !interval.is_zero());
+    }
+
+    /// Sets the version in which the states are outputted, which defaults to [`WireVersion::V1`].
+    pub fn set_wire_version(&mut self, wire_version: WireVersion) {
+        self.wire_version = wire_version;
     }
 
-    pub async fn run(mut self) {
+    /// Sets how the data whose output failed gets retried. It is dropped with `None`,
+    /// which is the default.
+    pub fn set_retry_config(&mut self, retry_config: Option<RetryConfig>) {
+        self.retry_config = retry_config;
+    }
+
+    /// Counters of the retried data, shared by every sink.
+    pub fn retry_stats(&self) -> Arc<RetryStats> {
+        self.retry_stats.clone()
+    }
+
+    /// Spawns the tracker into the current runtime, returning a handle
+    /// which can be used to stop it.
+    pub fn spawn(self) -> TrackerHandle {
+        let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel();
+
+        let retry_stats = self.retry_stats();
+        let join_handle =
+            tokio::spawn(self.run_until(tracker_handle::shutdown_requested(shutdown_receiver)));
+
+        TrackerHandle::new(shutdown_sender, join_handle, retry_stats)
+    }
+
+    /// Outputs the received data until every sender has been dropped.
+    pub async fn run(self) {
+        self.run_until(std::future::pending()).await;
+    }
+
+    /// Outputs the received data until every sender has been dropped or
+    /// `shutdown` completes, whichever happens first.
+    ///
+    /// On shutdown, the data which is already queued gets outputted before returning.
+    /// In both cases a final record with the id [`TRACKER_ID`] and the [`State::Stopping`]
+    /// state is outputted, signaling that the tracker has stopped. [`WireVersion::V1`]
+    /// receivers get it as [`State::Idle`], like any other `Stopping` state.
+    pub async fn run_until(mut self, shutdown: impl Future<Output = ()>) {
+        tokio::pin!(shutdown);
+
+        let sink_workers: Vec<SinkWorker> =
+            self.sinks
+                .drain(..)
+                .map(|sink| {
+                    let retry_queue = self.retry_config.as_ref().map(|retry_config| {
+                        RetryQueue::new(retry_config, self.retry_stats.clone())
+                    });
+
+                    SinkWorker::spawn(sink, retry_queue)
+                })
+                .collect();
+
+        let mut heartbeat = self.heartbeat_interval.map(|period| {
+            let mut heartbeat = tokio::time::interval_at(Instant::now() + period, period);
+            heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            heartbeat
+        });
+
         loop {
-            match self.receiver.recv().await {
-                Some(tracked_data) => {
-                    match serde_json::to_vec(&tracked_data) {
-                        Ok(serialized_data) => {
-                            match self
-                                .output_sender
-                                .send_to(serialized_data.as_slice(), &self.output_receiver_path)
-                                .await
-                            {
-                                Ok(_) => {
-                                    log::info!("sent data to output socket");
-                                }
-                                Err(error) => {
-                                    log::error!("failed to write to output socket: {}", error)
-                                }
-                            }
+            tokio::select! {
+                received = self.receiver.recv() => match received {
+                    Some(tracked_data) => {
+                        if heartbeat.is_some() {
+                            self.latest_tracked_data
+                                .insert(tracked_data.id.clone(), tracked_data.clone());
                         }
-                        Err(error) => log::error!("failed to serialize tracked data: {}", error),
-                    };
+
+                        self.output(&sink_workers, &tracked_data);
+                    }
+                    None => break,
+                },
+                _ = next_heartbeat(&mut heartbeat) => {
+                    let heartbeats: Vec<TrackedData> = self
+                        .latest_tracked_data
+                        .values()
+                        .map(TrackedData::to_heartbeat)
+                        .collect();
+
+                    for tracked_data in heartbeats {
+                        self.output(&sink_workers, &tracked_data);
+                    }
+                }
+                _ = &mut shutdown => {
+                    self.receiver.close();
+
+                    while let Some(tracked_data) = self.receiver.recv().await {
+                        self.output(&sink_workers, &tracked_data);
+                    }
+
+                    break;
                 }
-                None => (),
             }
         }
+
+        self.output(
+            &sink_workers,
+            &tracked_data::generate_state_tracking_data(TRACKER_ID, State::Stopping),
+        );
+
+        futures::future::join_all(sink_workers.into_iter().map(SinkWorker::stop)).await;
+        self.socket_files.clear();
+
+        log::info!("state tracker stopped");
+    }
+
+    fn output(&mut self, sink_workers: &[SinkWorker], tracked_data: &TrackedData) {
+        let mut tracked_data = tracked_data.to_wire_version(self.wire_version);
+        tracked_data.envelope = Some(Envelope {
+            schema_version: SCHEMA_VERSION,
+            instance_id: self.instance_id,
+            booted_at: self.booted_at,
+            sequence: self.next_sequence,
+        });
+        self.next_sequence += 1;
+
+        let tracked_data = Arc::new(tracked_data);
+
+        for sink_worker in sink_workers {
+            sink_worker.deliver(tracked_data.clone());
+        }
+    }
+}
+
+async fn next_heartbeat(heartbeat: &mut Option<Interval>) {
+    match heartbeat {
+        Some(heartbeat) => {
+            heartbeat.tick().await;
+        }
+        None => std::future::pending().await,
     }
 }
 
 #[cfg(test)]
-use crate::state::State;
-use std::time::{Duration, SystemTime};
-use tokio::io::AsyncWriteExt;
 use tokio::time::timeout;
 
 #[tokio::test]
@@ -84,8 +247,8 @@ async fn correct_output_retrieved() {
     const RECEIVER_PATH: &str = "/tmp/cooplan_state_tracker_test_receiver.sock";
     const TEST_ID: &str = "test_id";
 
-    tokio::fs::remove_file(SENDER_PATH).await;
-    tokio::fs::remove_file(RECEIVER_PATH).await;
+    let _ = tokio::fs::remove_file(SENDER_PATH).await;
+    let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
 
     let (sender, receiver) = tokio::sync::mpsc::channel(1024);
 
@@ -117,3 +280,337 @@ async fn correct_output_retrieved() {
     assert_eq!(tracker_data.id, TEST_ID);
     assert_eq!(tracker_data.state, State::Idle);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use async_trait::async_trait;
+    use tokio::net::UnixDatagram;
+    use tokio::sync::mpsc::Sender;
+    use tokio::time::timeout;
+
+    struct ChannelSink {
+        sender: Sender<TrackedData>,
+        closed: Sender<()>,
+    }
+
+    #[async_trait]
+    impl OutputSink for ChannelSink {
+        async fn emit(&mut self, tracked_data: &TrackedData) -> Result<(), Error> {
+            self.sender.send(tracked_data.clone()).await.unwrap();
+            Ok(())
+        }
+
+        async fn close(&mut self) -> Result<(), Error> {
+            self.closed.send(()).await.unwrap();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn stops_when_every_sender_is_dropped() {
+        const SENDER_PATH: &str = "/tmp/cooplan_state_tracker_test_stop_sender.sock";
+        const RECEIVER_PATH: &str = "/tmp/cooplan_state_tracker_test_stop_receiver.sock";
+
+        let _ = tokio::fs::remove_file(SENDER_PATH).await;
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+
+        let (sender, receiver) = tokio::sync::mpsc::channel::<TrackedData>(1024);
+
+        let output_receiver = tokio::net::UnixDatagram::bind(RECEIVER_PATH).unwrap();
+
+        let mut state_tracker =
+            StateTracker::try_new(SENDER_PATH, RECEIVER_PATH, receiver).unwrap();
+        state_tracker.set_wire_version(WireVersion::V2);
+
+        drop(sender);
+
+        timeout(Duration::from_secs(3), state_tracker.run())
+            .await
+            .expect("state tracker should have stopped");
+
+        let mut buffer = [0; 1024];
+        let length = output_receiver.recv(&mut buffer).await.unwrap();
+        let tracker_data = serde_json::from_slice::<TrackedData>(&buffer[..length]).unwrap();
+
+        assert_eq!(tracker_data.id, TRACKER_ID);
+        assert_eq!(tracker_data.state, State::Stopping);
+    }
+
+    #[tokio::test]
+    async fn heartbeats_repeat_latest_state() {
+        const SENDER_PATH: &str = "/tmp/cooplan_state_tracker_test_heartbeat_sender.sock";
+        const RECEIVER_PATH: &str = "/tmp/cooplan_state_tracker_test_heartbeat_receiver.sock";
+        const TEST_ID: &str = "test_id";
+
+        let _ = tokio::fs::remove_file(SENDER_PATH).await;
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+
+        let (sender, receiver) = tokio::sync::mpsc::channel(1024);
+
+        let output_receiver = tokio::net::UnixDatagram::bind(RECEIVER_PATH).unwrap();
+
+        let mut state_tracker =
+            StateTracker::try_new(SENDER_PATH, RECEIVER_PATH, receiver).unwrap();
+        state_tracker.set_heartbeat_interval(Some(Duration::from_millis(100)));
+
+        tokio::spawn(state_tracker.run());
+
+        sender
+            .send(TrackedData::new(
+                TEST_ID.to_string(),
+                State::Valid,
+                SystemTime::now(),
+            ))
+            .await
+            .expect("failed to send data");
+
+        let mut buffer = [0; 1024];
+
+        for heartbeat in [false, true, true] {
+            let length = timeout(Duration::from_secs(3), output_receiver.recv(&mut buffer))
+                .await
+                .unwrap()
+                .unwrap();
+            let tracker_data = serde_json::from_slice::<TrackedData>(&buffer[..length]).unwrap();
+
+            assert_eq!(tracker_data.id, TEST_ID);
+            assert_eq!(tracker_data.state, State::Valid);
+            assert_eq!(tracker_data.heartbeat, heartbeat);
+        }
+    }
+
+    #[tokio::test]
+    async fn outputs_to_every_sink() {
+        const TEST_ID: &str = "test_id";
+
+        let (sender, receiver) = tokio::sync::mpsc::channel(1024);
+        let (first_sender, mut first_receiver) = tokio::sync::mpsc::channel(1024);
+        let (second_sender, mut second_receiver) = tokio::sync::mpsc::channel(1024);
+        let (closed_sender, mut closed_receiver) = tokio::sync::mpsc::channel(1024);
+
+        let mut state_tracker = StateTracker::new(
+            receiver,
+            vec![Box::new(ChannelSink {
+                sender: first_sender,
+                closed: closed_sender.clone(),
+            })],
+        );
+        state_tracker.add_sink(Box::new(ChannelSink {
+            sender: second_sender,
+            closed: closed_sender,
+        }));
+        let instance_id = state_tracker.instance_id();
+
+        sender
+            .send(TrackedData::new(
+                TEST_ID.to_string(),
+                State::Valid,
+                SystemTime::now(),
+            ))
+            .await
+            .unwrap();
+        drop(sender);
+
+        timeout(Duration::from_secs(3), state_tracker.run())
+            .await
+            .expect("state tracker should have stopped");
+
+        for receiver in [&mut first_receiver, &mut second_receiver] {
+            for (sequence, id) in [TEST_ID, TRACKER_ID].into_iter().enumerate() {
+                let tracked_data = receiver.recv().await.unwrap();
+                let envelope = tracked_data.envelope.unwrap();
+
+                assert_eq!(tracked_data.id, id);
+                assert_eq!(envelope.instance_id, instance_id);
+                assert_eq!(envelope.sequence, sequence as u64);
+            }
+        }
+
+        for _ in 0..2 {
+            closed_receiver.recv().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn zero_heartbeat_interval_disables_heartbeats() {
+        const TEST_ID: &str = "test_id";
+
+        let (sender, receiver) = tokio::sync::mpsc::channel(1024);
+        let (output_sender, mut output_receiver) = tokio::sync::mpsc::channel(1024);
+        let (closed_sender, _closed_receiver) = tokio::sync::mpsc::channel(1024);
+
+        let mut state_tracker = StateTracker::new(
+            receiver,
+            vec![Box::new(ChannelSink {
+                sender: output_sender,
+                closed: closed_sender,
+            })],
+        );
+        state_tracker.set_heartbeat_interval(Some(Duration::ZERO));
+
+        sender
+            .send(TrackedData::new(
+                TEST_ID.to_string(),
+                State::Valid,
+                SystemTime::now(),
+            ))
+            .await
+            .unwrap();
+        drop(sender);
+
+        timeout(Duration::from_secs(3), state_tracker.run())
+            .await
+            .expect("state tracker should have stopped");
+
+        for id in [TEST_ID, TRACKER_ID] {
+            let tracked_data = output_receiver.recv().await.unwrap();
+
+            assert_eq!(tracked_data.id, id);
+            assert!(!tracked_data.heartbeat);
+        }
+        assert!(output_receiver.recv().await.is_none());
+    }
+
+    struct StuckSink;
+
+    #[async_trait]
+    impl OutputSink for StuckSink {
+        async fn emit(&mut self, _: &TrackedData) -> Result<(), Error> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn stuck_or_failing_sinks_do_not_block_others() {
+        const SENDER_PATH: &str = "/tmp/cooplan_state_tracker_test_fan_out_sender.sock";
+        const RECEIVER_PATH: &str = "/tmp/cooplan_state_tracker_test_fan_out_receiver.sock";
+        const TEST_ID: &str = "test_id";
+
+        let _ = tokio::fs::remove_file(SENDER_PATH).await;
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+
+        let (sender, receiver) = tokio::sync::mpsc::channel(1024);
+
+        let output_receiver = UnixDatagram::bind(RECEIVER_PATH).unwrap();
+        let output_sender = Arc::new(UnixDatagram::bind(SENDER_PATH).unwrap());
+
+        let state_tracker = StateTracker::new(
+            receiver,
+            vec![
+                Box::new(StuckSink),
+                Box::new(UnixDatagramSink::new(
+                    output_sender.clone(),
+                    "/tmp/cooplan_missing_receiver.sock",
+                )),
+                Box::new(UnixDatagramSink::new(output_sender, RECEIVER_PATH)),
+            ],
+        );
+
+        tokio::spawn(state_tracker.run());
+
+        for _ in 0..3 {
+            sender
+                .send(TrackedData::new(
+                    TEST_ID.to_string(),
+                    State::Valid,
+                    SystemTime::now(),
+                ))
+                .await
+                .unwrap();
+        }
+
+        let mut buffer = [0; 1024];
+
+        for _ in 0..3 {
+            let length = timeout(Duration::from_secs(3), output_receiver.recv(&mut buffer))
+                .await
+                .unwrap()
+                .unwrap();
+            let tracker_data = serde_json::from_slice::<TrackedData>(&buffer[..length]).unwrap();
+
+            assert_eq!(tracker_data.id, TEST_ID);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_failed_outputs() {
+        const SENDER_PATH: &str = "/tmp/cooplan_state_tracker_test_retry_sender.sock";
+        const RECEIVER_PATH: &str = "/tmp/cooplan_state_tracker_test_retry_receiver.sock";
+        const TEST_ID: &str = "test_id";
+
+        let _ = tokio::fs::remove_file(SENDER_PATH).await;
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+
+        let (sender, receiver) = tokio::sync::mpsc::channel(1024);
+
+        let mut state_tracker =
+            StateTracker::try_new(SENDER_PATH, RECEIVER_PATH, receiver).unwrap();
+        state_tracker.set_retry_config(Some(RetryConfig {
+            initial_delay_in_milliseconds: 50,
+            max_delay_in_milliseconds: 50,
+            ..RetryConfig::default()
+        }));
+        let retry_stats = state_tracker.retry_stats();
+
+        tokio::spawn(state_tracker.run());
+
+        for state in [
+            State::Valid,
+            State::Error("error".to_string()),
+            State::Valid,
+        ] {
+            sender
+                .send(TrackedData::new(
+                    TEST_ID.to_string(),
+                    state,
+                    SystemTime::now(),
+                ))
+                .await
+                .unwrap();
+        }
+
+        // Time stands still until every state has failed and waits to be retried.
+        while retry_stats.coalesced() < 1 {
+            tokio::task::yield_now().await;
+        }
+        let output_receiver = UnixDatagram::bind(RECEIVER_PATH).unwrap();
+
+        let mut buffer = [0; 1024];
+        for state in [State::Error("error".to_string()), State::Valid] {
+            let length = timeout(Duration::from_secs(3), output_receiver.recv(&mut buffer))
+                .await
+                .unwrap()
+                .unwrap();
+            let tracker_data = serde_json::from_slice::<TrackedData>(&buffer[..length]).unwrap();
+
+            assert_eq!(tracker_data.state, state);
+        }
+
+        assert_eq!(retry_stats.retried(), 2);
+        assert_eq!(retry_stats.coalesced(), 1);
+        assert_eq!(retry_stats.dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn bind_failures_are_classified() {
+        const SENDER_PATH: &str = "/tmp/cooplan_state_tracker_test_in_use_sender.sock";
+
+        let _ = tokio::fs::remove_file(SENDER_PATH).await;
+        let _bound_socket = UnixDatagram::bind(SENDER_PATH).unwrap();
+
+        let (_, receiver) = tokio::sync::mpsc::channel(1);
+        let error = StateTracker::try_new(SENDER_PATH, "", receiver)
+            .err()
+            .expect("bind should have failed");
+        assert_eq!(error.kind(), ErrorKind::SocketPathInUse);
+
+        let (_, receiver) = tokio::sync::mpsc::channel(1);
+        let error =
+            StateTracker::try_new("/tmp/cooplan_missing_directory/sender.sock", "", receiver)
+                .err()
+                .expect("bind should have failed");
+        assert_eq!(error.kind(), ErrorKind::ParentDirectoryMissing);
+    }
+}
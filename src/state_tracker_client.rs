@@ -1,17 +1,38 @@
+use crate::encoding::PayloadFormat;
 use crate::error::{Error, ErrorKind};
+use crate::error_info::ErrorInfo;
+use crate::labels;
+use crate::output_sink::OutputSink;
+use crate::socket_file;
+use crate::spooling_sink::SpoolingSink;
 use crate::state::State;
 use crate::state_tracker::StateTracker;
 use crate::state_tracking_config::StateTrackingConfig;
 use crate::tracked_data;
 use crate::tracked_data::TrackedData;
+use crate::tracker_handle::TrackerHandle;
+use crate::unix_datagram_sink::UnixDatagramSink;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::net::UnixDatagram;
 use tokio::time::Instant;
 
+/// Latest state emitted for a component.
+struct LatestUpdate {
+    state: State,
+    error_info: Option<ErrorInfo>,
+    instant: Instant,
+}
+
 #[derive(Clone)]
 pub struct StateTrackerClient {
     id: String,
     state_sender: tokio::sync::mpsc::Sender<TrackedData>,
-    latest_update: Instant,
+    // Shared between clones so that every client reporting an id throttles it alike.
+    latest_updates: Arc<Mutex<HashMap<String, LatestUpdate>>>,
     update_interval_in_seconds: u64,
+    labels: BTreeMap<String, String>,
 }
 
 impl StateTrackerClient {
@@ -23,8 +44,9 @@ impl StateTrackerClient {
         StateTrackerClient {
             id,
             state_sender,
-            latest_update: Instant::now(),
+            latest_updates: Arc::new(Mutex::new(HashMap::new())),
             update_interval_in_seconds,
+            labels: BTreeMap::new(),
         }
     }
 
@@ -32,15 +54,77 @@ impl StateTrackerClient {
         self.id = id;
     }
 
+    /// Sets a label which will be added to every state sent by the client.
+    pub fn set_label(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.labels.insert(key.into(), value.into());
+    }
+
+    pub fn remove_label(&mut self, key: &str) {
+        self.labels.remove(key);
+    }
+
+    /// Sends the state of the component identified by the client's id.
+    ///
+    /// Urgent states, such as errors, and state changes are sent immediately, whereas repeating
+    /// the latest sent state is ignored until the update interval elapses.
     pub async fn send_state(&self, state: State) -> Result<(), Error> {
-        // Avoid spamming Idle & Valid states.
-        if !state.is_error()
-            && self.latest_update.elapsed().as_secs() < self.update_interval_in_seconds
-        {
+        if !state.is_urgent() && self.is_throttled(&state) {
+            return Ok(());
+        }
+
+        self.send(state, None, BTreeMap::new()).await
+    }
+
+    /// Sends the state like [`StateTrackerClient::send_state`], with `labels` taking
+    /// precedence over the client's labels.
+    pub async fn send_state_with_labels(
+        &self,
+        state: State,
+        labels: BTreeMap<String, String>,
+    ) -> Result<(), Error> {
+        if !state.is_urgent() && self.is_throttled(&state) {
             return Ok(());
         }
 
-        let tracked_data = tracked_data::generate_state_tracking_data(&self.id, state);
+        self.send(state, None, labels).await
+    }
+
+    /// Sends an error state whose details are built from `error`.
+    pub async fn send_error(&self, error: &(dyn std::error::Error + 'static)) -> Result<(), Error> {
+        self.send_error_info(ErrorInfo::from_error(error)).await
+    }
+
+    /// Sends an error state with the provided details, counting how many
+    /// consecutive times an error with the same code has been sent.
+    pub async fn send_error_info(&self, mut error_info: ErrorInfo) -> Result<(), Error> {
+        if let Some(LatestUpdate {
+            error_info: Some(latest_error_info),
+            ..
+        }) = self.latest_updates().get(&self.id)
+        {
+            if latest_error_info.code == error_info.code {
+                error_info.occurrences = latest_error_info.occurrences + 1;
+            }
+        }
+
+        self.send(
+            State::Error(error_info.message.clone()),
+            Some(error_info),
+            BTreeMap::new(),
+        )
+        .await
+    }
+
+    async fn send(
+        &self,
+        state: State,
+        error_info: Option<ErrorInfo>,
+        labels: BTreeMap<String, String>,
+    ) -> Result<(), Error> {
+        let mut tracked_data = tracked_data::generate_state_tracking_data(&self.id, state.clone());
+        tracked_data.error_info = error_info.clone();
+        tracked_data.labels = self.labels.clone();
+        tracked_data.labels.extend(labels);
 
         match self.state_sender.send(tracked_data).await {
             Ok(_) => (),
@@ -52,36 +136,139 @@ impl StateTrackerClient {
             }
         }
 
+        self.latest_updates().insert(
+            self.id.clone(),
+            LatestUpdate {
+                state,
+                error_info,
+                instant: Instant::now(),
+            },
+        );
+
         Ok(())
     }
-}
 
+    fn is_throttled(&self, state: &State) -> bool {
+        match self.latest_updates().get(&self.id) {
+            Some(latest_update) => {
+                latest_update.state == *state
+                    && latest_update.instant.elapsed().as_secs() < self.update_interval_in_seconds
+            }
+            None => false,
+        }
+    }
+
+    fn latest_updates(&self) -> MutexGuard<'_, HashMap<String, LatestUpdate>> {
+        self.latest_updates
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
 
+/// Binds the output socket, if any, and spawns a StateTracker based on the configuration,
+/// returning a client which reports to it and a handle which can be used to stop it.
 pub async fn build(
     state_tracking_config: StateTrackingConfig,
     state_tracking_channel_boundary: usize,
-) -> StateTrackerClient {
+) -> Result<(StateTrackerClient, TrackerHandle), Error> {
     let (state_sender, state_receiver) =
         tokio::sync::mpsc::channel(state_tracking_channel_boundary);
 
-    let state_update_interval = state_tracking_config.state_sender_interval_in_seconds;
+    if state_tracking_config.state_output_receiver_path.is_none() {
+        if state_tracking_config.state_outputs.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InternalFailure,
+                "neither state_output_receiver_path nor state_outputs are configured",
+            ));
+        }
+
+        if state_tracking_config.state_output_spool.is_some() {
+            return Err(Error::new(
+                ErrorKind::InternalFailure,
+                "state_output_spool requires state_output_receiver_path",
+            ));
+        }
+    }
 
-    tokio::spawn(async move {
-        let state_tracker = match StateTracker::try_new(
-            state_tracking_config.state_output_sender_path.as_str(),
-            state_tracking_config.state_output_receiver_path.as_str(),
-            state_receiver,
-        ) {
-            Ok(state_tracker) => state_tracker,
+    let (output_sender, socket_file) = match &state_tracking_config.state_output_sender_path {
+        Some(output_sender_path) => {
+            let (output_sender, socket_file) = socket_file::bind(
+                output_sender_path,
+                &state_tracking_config.state_output_socket,
+            )?;
+
+            (output_sender, Some(socket_file))
+        }
+        None => match UnixDatagram::unbound() {
+            Ok(output_sender) => (output_sender, None),
             Err(error) => {
-                panic!("failed to initialize state tracker: {}", error);
+                return Err(Error::new(
+                    ErrorKind::InternalFailure,
+                    format!("failed to create output socket: {}", error),
+                ))
             }
-        };
+        },
+    };
+    let output_sender = Arc::new(output_sender);
+
+    let payload_format = PayloadFormat {
+        encoding: state_tracking_config.state_encoding,
+        timestamp_format: state_tracking_config.state_timestamp_format,
+        compression: state_tracking_config.state_compression,
+        signing_key: match &state_tracking_config.state_signing {
+            Some(signing_config) => Some(signing_config.load_key()?),
+            None => None,
+        },
+    };
+    let oversize_config = state_tracking_config.state_oversize;
 
-        state_tracker.run().await;
-    });
+    let mut sinks: Vec<Box<dyn OutputSink>> = Vec::new();
+    if let Some(output_receiver_path) = &state_tracking_config.state_output_receiver_path {
+        let sink = UnixDatagramSink::new(output_sender.clone(), output_receiver_path)
+            .with_payload_format(payload_format.clone())
+            .with_oversize_config(oversize_config);
+
+        match state_tracking_config.state_output_spool {
+            Some(spool_config) => sinks.push(Box::new(SpoolingSink::try_new(sink, spool_config)?)),
+            None => sinks.push(Box::new(sink)),
+        }
+    }
+    for output_config in state_tracking_config.state_outputs.iter() {
+        sinks.push(output_config.build_sink(
+            &output_sender,
+            payload_format.clone(),
+            oversize_config,
+        )?);
+    }
+
+    let mut state_tracker = StateTracker::new(state_receiver, sinks);
+    // Nothing fails from now on, so the socket file is no longer removed as if it had.
+    if let Some(mut socket_file) = socket_file {
+        socket_file.complete_setup();
+        state_tracker.add_socket_file(socket_file);
+    }
+    state_tracker.set_heartbeat_interval(
+        state_tracking_config
+            .state_heartbeat_interval_in_seconds
+            .map(Duration::from_secs),
+    );
+    state_tracker.set_wire_version(state_tracking_config.state_wire_version);
+    state_tracker.set_retry_config(state_tracking_config.state_output_retry);
+
+    let mut state_tracker_client = StateTrackerClient::new(
+        "default".to_string(),
+        state_sender,
+        state_tracking_config.state_sender_interval_in_seconds,
+    );
+    state_tracker_client.labels = labels::process_labels(
+        state_tracking_config.service_name.as_deref(),
+        state_tracking_config.service_version.as_deref(),
+    );
+    state_tracker_client
+        .labels
+        .extend(state_tracking_config.state_labels);
 
-    StateTrackerClient::new("default".to_string(), state_sender, state_update_interval)
+    Ok((state_tracker_client, state_tracker.spawn()))
 }
 
 #[cfg(test)]
@@ -96,6 +283,9 @@ pub async fn avoids_spamming_idle_and_active_states() {
         StateTrackerClient::new(ID.to_string(), state_sender, UPDATE_INTERVAL_IN_SECONDS);
 
     state_tracker_client.send_state(State::Valid).await.unwrap();
+    state_tracker_client.send_state(State::Valid).await.unwrap();
+
+    assert_eq!(state_receiver.try_recv().unwrap().state, State::Valid);
 
     match state_receiver.try_recv() {
         Ok(_) => panic!("should not have received a state"),
@@ -127,6 +317,320 @@ pub async fn error_state_is_instantly_set() {
             }
             _ => panic!("should have received an error state"),
         },
-        Err(error) => panic!("should have received a state"),
+        Err(_) => panic!("should have received a state"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_sink::FileSinkConfig;
+    use crate::output_config::OutputConfig;
+    use crate::signing::SigningConfig;
+
+    #[tokio::test(start_paused = true)]
+    pub async fn repeated_state_is_sent_after_interval() {
+        const ID: &str = "ID";
+        const UPDATE_INTERVAL_IN_SECONDS: u64 = 5;
+
+        let (state_sender, mut state_receiver) = tokio::sync::mpsc::channel::<TrackedData>(5);
+
+        let state_tracker_client =
+            StateTrackerClient::new(ID.to_string(), state_sender, UPDATE_INTERVAL_IN_SECONDS);
+
+        state_tracker_client.send_state(State::Valid).await.unwrap();
+        tokio::time::advance(Duration::from_secs(UPDATE_INTERVAL_IN_SECONDS)).await;
+        state_tracker_client.send_state(State::Valid).await.unwrap();
+
+        assert_eq!(state_receiver.try_recv().unwrap().state, State::Valid);
+        assert_eq!(state_receiver.try_recv().unwrap().state, State::Valid);
+    }
+
+    #[tokio::test]
+    pub async fn state_change_is_instantly_set() {
+        const ID: &str = "ID";
+        const UPDATE_INTERVAL_IN_SECONDS: u64 = 5;
+
+        let (state_sender, mut state_receiver) = tokio::sync::mpsc::channel::<TrackedData>(5);
+
+        let state_tracker_client =
+            StateTrackerClient::new(ID.to_string(), state_sender, UPDATE_INTERVAL_IN_SECONDS);
+
+        state_tracker_client.send_state(State::Idle).await.unwrap();
+        state_tracker_client.send_state(State::Valid).await.unwrap();
+
+        assert_eq!(state_receiver.try_recv().unwrap().state, State::Idle);
+        assert_eq!(state_receiver.try_recv().unwrap().state, State::Valid);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    pub async fn throttles_each_id_separately() {
+        const FIRST_ID: &str = "FIRST_ID";
+        const SECOND_ID: &str = "SECOND_ID";
+        const UPDATE_INTERVAL_IN_SECONDS: u64 = 5;
+
+        let (state_sender, mut state_receiver) = tokio::sync::mpsc::channel::<TrackedData>(5);
+
+        let first_client = StateTrackerClient::new(
+            FIRST_ID.to_string(),
+            state_sender,
+            UPDATE_INTERVAL_IN_SECONDS,
+        );
+        let mut second_client = first_client.clone();
+        second_client.set_id(SECOND_ID.to_string());
+
+        first_client.send_state(State::Valid).await.unwrap();
+        second_client.send_state(State::Valid).await.unwrap();
+        first_client.clone().send_state(State::Valid).await.unwrap();
+
+        assert_eq!(state_receiver.try_recv().unwrap().id, FIRST_ID);
+        assert_eq!(state_receiver.try_recv().unwrap().id, SECOND_ID);
+        assert!(state_receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    pub async fn degraded_state_is_never_throttled() {
+        const ID: &str = "ID";
+        const UPDATE_INTERVAL_IN_SECONDS: u64 = 5;
+        const MESSAGE: &str = "TEST_DEGRADED";
+
+        let (state_sender, mut state_receiver) = tokio::sync::mpsc::channel::<TrackedData>(5);
+
+        let state_tracker_client =
+            StateTrackerClient::new(ID.to_string(), state_sender, UPDATE_INTERVAL_IN_SECONDS);
+
+        for _ in 0..2 {
+            state_tracker_client
+                .send_state(State::Degraded(MESSAGE.to_string()))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                state_receiver.try_recv().unwrap().state,
+                State::Degraded(MESSAGE.to_string())
+            );
+        }
+    }
+
+    #[tokio::test]
+    pub async fn counts_error_occurrences() {
+        const ID: &str = "ID";
+        const UPDATE_INTERVAL_IN_SECONDS: u64 = 5;
+
+        let (state_sender, mut state_receiver) = tokio::sync::mpsc::channel::<TrackedData>(5);
+
+        let state_tracker_client =
+            StateTrackerClient::new(ID.to_string(), state_sender, UPDATE_INTERVAL_IN_SECONDS);
+
+        let error = std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out");
+        state_tracker_client.send_error(&error).await.unwrap();
+        state_tracker_client.send_error(&error).await.unwrap();
+
+        for occurrences in [1, 2] {
+            let tracked_data = state_receiver.try_recv().unwrap();
+            let error_info = tracked_data.error_info.unwrap();
+
+            assert_eq!(tracked_data.state, State::Error("timed out".to_string()));
+            assert_eq!(error_info.code, "io::TimedOut");
+            assert_eq!(error_info.occurrences, occurrences);
+        }
+
+        state_tracker_client
+            .send_error_info(ErrorInfo::new("other", "other error"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            state_receiver
+                .try_recv()
+                .unwrap()
+                .error_info
+                .unwrap()
+                .occurrences,
+            1
+        );
+    }
+
+    #[tokio::test]
+    pub async fn merges_labels() {
+        const ID: &str = "ID";
+        const UPDATE_INTERVAL_IN_SECONDS: u64 = 5;
+
+        let (state_sender, mut state_receiver) = tokio::sync::mpsc::channel::<TrackedData>(5);
+
+        let mut state_tracker_client =
+            StateTrackerClient::new(ID.to_string(), state_sender, UPDATE_INTERVAL_IN_SECONDS);
+        state_tracker_client.set_label("shard", "1");
+        state_tracker_client.set_label("zone", "a");
+
+        state_tracker_client
+            .send_state_with_labels(
+                State::Valid,
+                BTreeMap::from([("zone".to_string(), "b".to_string())]),
+            )
+            .await
+            .unwrap();
+
+        let labels = state_receiver.try_recv().unwrap().labels;
+        assert_eq!(labels.get("shard").unwrap(), "1");
+        assert_eq!(labels.get("zone").unwrap(), "b");
+    }
+
+    #[tokio::test]
+    pub async fn tracker_stops_through_handle() {
+        const SENDER_PATH: &str = "/tmp/cooplan_state_tracker_client_test_sender.sock";
+        const RECEIVER_PATH: &str = "/tmp/cooplan_state_tracker_client_test_receiver.sock";
+        const SECOND_RECEIVER_PATH: &str =
+            "/tmp/cooplan_state_tracker_client_test_second_receiver.sock";
+
+        let _ = tokio::fs::remove_file(SENDER_PATH).await;
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+        let _ = tokio::fs::remove_file(SECOND_RECEIVER_PATH).await;
+
+        let output_receiver = tokio::net::UnixDatagram::bind(RECEIVER_PATH).unwrap();
+        let second_output_receiver = tokio::net::UnixDatagram::bind(SECOND_RECEIVER_PATH).unwrap();
+
+        let (state_tracker_client, tracker_handle) = build(
+            StateTrackingConfig {
+                state_output_sender_path: Some(SENDER_PATH.to_string()),
+                state_output_receiver_path: Some(RECEIVER_PATH.to_string()),
+                state_outputs: vec![OutputConfig::UnixDatagram {
+                    receiver_path: SECOND_RECEIVER_PATH.to_string(),
+                }],
+                state_sender_interval_in_seconds: 5,
+                service_name: Some("service".to_string()),
+                ..Default::default()
+            },
+            5,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            state_tracker_client.labels.get(labels::PID_LABEL).unwrap(),
+            &std::process::id().to_string()
+        );
+        assert_eq!(
+            state_tracker_client
+                .labels
+                .get(labels::SERVICE_NAME_LABEL)
+                .unwrap(),
+            "service"
+        );
+
+        tokio::time::timeout(Duration::from_secs(3), tracker_handle.shutdown())
+            .await
+            .expect("state tracker should have stopped")
+            .unwrap();
+
+        let mut buffer = [0; 1024];
+
+        for output_receiver in [output_receiver, second_output_receiver] {
+            let length = output_receiver.recv(&mut buffer).await.unwrap();
+            let tracked_data = serde_json::from_slice::<TrackedData>(&buffer[..length]).unwrap();
+
+            assert_eq!(tracked_data.id, crate::state_tracker::TRACKER_ID);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn outputs_to_file_instead_of_socket() {
+        const PATH: &str = "/tmp/cooplan_state_tracker_client_test_states.jsonl";
+        const ID: &str = "ID";
+
+        let _ = tokio::fs::remove_file(PATH).await;
+
+        let (mut state_tracker_client, tracker_handle) = build(
+            StateTrackingConfig {
+                state_outputs: vec![OutputConfig::File(FileSinkConfig::new(PATH))],
+                state_sender_interval_in_seconds: 5,
+                ..Default::default()
+            },
+            5,
+        )
+        .await
+        .unwrap();
+
+        state_tracker_client.set_id(ID.to_string());
+        state_tracker_client.send_state(State::Valid).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(3), tracker_handle.shutdown())
+            .await
+            .expect("state tracker should have stopped")
+            .unwrap();
+
+        let ids: Vec<String> = tokio::fs::read_to_string(PATH)
+            .await
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<TrackedData>(line).unwrap().id)
+            .collect();
+        assert_eq!(ids, vec![ID, crate::state_tracker::TRACKER_ID]);
+    }
+
+    #[tokio::test]
+    pub async fn build_fails_without_outputs() {
+        let result = build(
+            StateTrackingConfig {
+                state_sender_interval_in_seconds: 5,
+                ..Default::default()
+            },
+            5,
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    pub async fn build_fails_when_socket_path_is_in_use() {
+        const SENDER_PATH: &str = "/tmp/cooplan_state_tracker_client_test_in_use_sender.sock";
+
+        let _ = tokio::fs::remove_file(SENDER_PATH).await;
+        let _bound_socket = tokio::net::UnixDatagram::bind(SENDER_PATH).unwrap();
+
+        let result = build(
+            StateTrackingConfig {
+                state_output_sender_path: Some(SENDER_PATH.to_string()),
+                state_output_receiver_path: Some("/tmp/cooplan_unused_receiver.sock".to_string()),
+                state_sender_interval_in_seconds: 5,
+                ..Default::default()
+            },
+            5,
+        )
+        .await;
+
+        match result {
+            Ok(_) => panic!("build should have failed"),
+            Err(error) => assert_eq!(error.kind(), ErrorKind::SocketPathInUse),
+        }
+    }
+
+    #[tokio::test]
+    pub async fn build_failure_leaves_no_socket_file() {
+        const SENDER_PATH: &str = "/tmp/cooplan_state_tracker_client_test_failed_sender.sock";
+
+        let _ = tokio::fs::remove_file(SENDER_PATH).await;
+
+        let state_tracking_config = || StateTrackingConfig {
+            state_output_sender_path: Some(SENDER_PATH.to_string()),
+            state_output_receiver_path: Some("/tmp/cooplan_unused_receiver.sock".to_string()),
+            state_sender_interval_in_seconds: 5,
+            ..Default::default()
+        };
+
+        let result = build(
+            StateTrackingConfig {
+                state_signing: Some(SigningConfig::new("/tmp/cooplan_missing_signing_key")),
+                ..state_tracking_config()
+            },
+            5,
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(!std::path::Path::new(SENDER_PATH).exists());
+
+        let (_, tracker_handle) = build(state_tracking_config(), 5).await.unwrap();
+        tracker_handle.shutdown().await.unwrap();
+    }
+}
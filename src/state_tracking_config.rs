@@ -1,9 +1,70 @@
+use crate::compression::CompressionConfig;
+use crate::encoding::Encoding;
+use crate::output_config::OutputConfig;
+use crate::oversize::OversizeConfig;
+use crate::retry::RetryConfig;
+use crate::signing::SigningConfig;
+use crate::socket_file::SocketFileConfig;
+use crate::spooling_sink::SpoolConfig;
+use crate::state::WireVersion;
+use crate::timestamp::TimestampFormat;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Default)]
 pub struct StateTrackingConfig {
-    pub state_output_sender_path: String,
-    pub state_output_receiver_path: String,
+    /// Path to which the socket sending the UnixDatagram outputs is bound.
+    /// An unbound socket is used when missing.
+    #[serde(default)]
+    pub state_output_sender_path: Option<String>,
+    /// Path to the UnixDatagram socket receiving the outputs. When missing, the states
+    /// are only outputted to `state_outputs`, e.g. to a file.
+    #[serde(default)]
+    pub state_output_receiver_path: Option<String>,
+    /// Handling of the file of the socket bound to `state_output_sender_path`.
+    #[serde(default)]
+    pub state_output_socket: SocketFileConfig,
+    /// Spool storing the states which could not be sent to `state_output_receiver_path`.
+    #[serde(default)]
+    pub state_output_spool: Option<SpoolConfig>,
+    /// Retrying of the states whose output failed, which are dropped when missing.
+    #[serde(default)]
+    pub state_output_retry: Option<RetryConfig>,
+    /// Further destinations to which the states are outputted.
+    #[serde(default)]
+    pub state_outputs: Vec<OutputConfig>,
 
     pub state_sender_interval_in_seconds: u64,
+    /// Interval at which the latest state of every component is repeated.
+    /// Heartbeats are disabled when missing or 0.
+    #[serde(default)]
+    pub state_heartbeat_interval_in_seconds: Option<u64>,
+    /// Version in which the states are outputted.
+    #[serde(default)]
+    pub state_wire_version: WireVersion,
+    /// Encoding of the outputs, except for those written to files.
+    #[serde(default)]
+    pub state_encoding: Encoding,
+    /// Format of the outputted timestamps.
+    #[serde(default)]
+    pub state_timestamp_format: TimestampFormat,
+    /// Compression of the large states, which are never compressed when missing.
+    #[serde(default)]
+    pub state_compression: Option<CompressionConfig>,
+    /// Signing of the states, so that collectors sharing the key can authenticate them.
+    #[serde(default)]
+    pub state_signing: Option<SigningConfig>,
+    /// Handling of the states which do not fit into a datagram.
+    #[serde(default)]
+    pub state_oversize: OversizeConfig,
+
+    /// Name of the service, added as a label to every state.
+    #[serde(default)]
+    pub service_name: Option<String>,
+    /// Version of the service, added as a label to every state.
+    #[serde(default)]
+    pub service_version: Option<String>,
+    /// Labels added to every state.
+    #[serde(default)]
+    pub state_labels: BTreeMap<String, String>,
 }
@@ -0,0 +1,123 @@
+use crate::encoding::PayloadFormat;
+use crate::error::Error;
+use crate::output_sink::OutputSink;
+use crate::reconnecting_stream::ReconnectingStream;
+use crate::tracked_data::TrackedData;
+
+use async_trait::async_trait;
+use futures::FutureExt;
+use tokio::net::TcpStream;
+
+/// Outputs every TrackedData as a frame, JSON by default, see [`crate::framing`], written to a
+/// TCP connection.
+///
+/// The connection gets established on the first emission and reestablished whenever
+/// the receiver restarts, waiting longer after every failed attempt.
+pub struct TcpSink {
+    stream: ReconnectingStream<TcpStream>,
+    payload_format: PayloadFormat,
+}
+
+impl TcpSink {
+    /// # Arguments
+    /// * `address` - Address of the receiver, such as `collector:7070`.
+    pub fn new(address: &str) -> Self {
+        let peer_address = address.to_string();
+
+        Self {
+            stream: ReconnectingStream::new(
+                address.to_string(),
+                Box::new(move || TcpStream::connect(peer_address.clone()).boxed()),
+            ),
+            payload_format: PayloadFormat::default(),
+        }
+    }
+
+    /// Sets how the outputs are serialized, which is as uncompressed JSON by default.
+    pub fn with_payload_format(mut self, payload_format: PayloadFormat) -> Self {
+        self.payload_format = payload_format;
+        self
+    }
+}
+
+#[async_trait]
+impl OutputSink for TcpSink {
+    async fn emit(&mut self, tracked_data: &TrackedData) -> Result<(), Error> {
+        self.stream
+            .write_frame(&self.payload_format.serialize(tracked_data)?)
+            .await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        self.stream.flush().await
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        self.stream.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing;
+    use crate::state::State;
+    use std::time::{Duration, SystemTime};
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn outputs_frames() {
+        const TEST_ID: &str = "test_id";
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut sink = TcpSink::new(&listener.local_addr().unwrap().to_string());
+
+        for state in [State::Idle, State::Valid] {
+            sink.emit(&TrackedData::new(
+                TEST_ID.to_string(),
+                state,
+                SystemTime::now(),
+            ))
+            .await
+            .unwrap();
+        }
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        for state in [State::Idle, State::Valid] {
+            let frame = framing::read_frame(&mut stream).await.unwrap().unwrap();
+            let tracked_data = serde_json::from_slice::<TrackedData>(&frame).unwrap();
+
+            assert_eq!(tracked_data.id, TEST_ID);
+            assert_eq!(tracked_data.state, state);
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_when_receiver_closes_connection() {
+        const TEST_ID: &str = "test_id";
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut sink = TcpSink::new(&listener.local_addr().unwrap().to_string());
+        let tracked_data = TrackedData::new(TEST_ID.to_string(), State::Valid, SystemTime::now());
+
+        sink.emit(&tracked_data).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        assert!(framing::read_frame(&mut stream).await.unwrap().is_some());
+
+        drop(stream);
+
+        sink.emit(&tracked_data).await.unwrap();
+        let (mut stream, _) = timeout(Duration::from_secs(3), listener.accept())
+            .await
+            .expect("sink should have reconnected")
+            .unwrap();
+        let frame = framing::read_frame(&mut stream).await.unwrap().unwrap();
+
+        assert_eq!(
+            serde_json::from_slice::<TrackedData>(&frame).unwrap().id,
+            TEST_ID
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Serialization of the timestamps of the outputted TrackedData.
+//!
+//! Timestamps are serialized in the [`TimestampFormat`] chosen for the output, see
+//! [`FormattedTimestamp`], while deserialization accepts any of them.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    /// `{"secs_since_epoch": .., "nanos_since_epoch": ..}`, as serialized by serde.
+    #[default]
+    Legacy,
+    /// RFC 3339 string in UTC with nanoseconds, such as `2024-01-01T00:00:00.000000000Z`.
+    Rfc3339,
+    /// Milliseconds since the Unix epoch.
+    UnixMillis,
+}
+
+/// Seconds since the Unix epoch from which timestamps are past the year 9999,
+/// which RFC 3339 cannot represent.
+const RFC3339_END_IN_SECONDS: u64 = 253_402_300_800;
+
+/// Timestamp serialized in a given format.
+#[derive(Clone, Copy, Debug)]
+pub struct FormattedTimestamp {
+    pub timestamp: SystemTime,
+    pub format: TimestampFormat,
+}
+
+impl Serialize for FormattedTimestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.format == TimestampFormat::Legacy {
+            return self.timestamp.serialize(serializer);
+        }
+
+        let duration = match self.timestamp.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration,
+            Err(_) => {
+                return Err(serde::ser::Error::custom(
+                    "timestamp is earlier than the Unix epoch",
+                ))
+            }
+        };
+
+        match self.format {
+            TimestampFormat::Rfc3339 if duration.as_secs() >= RFC3339_END_IN_SECONDS => Err(
+                serde::ser::Error::custom("timestamp is later than the year 9999"),
+            ),
+            TimestampFormat::Rfc3339 => {
+                serializer.collect_str(&humantime::format_rfc3339_nanos(self.timestamp))
+            }
+            _ => serializer.serialize_u64(duration.as_millis() as u64),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedTimestamp {
+    UnixMillis(u64),
+    Rfc3339(String),
+    Legacy(SystemTime),
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<SystemTime, D::Error> {
+    match SerializedTimestamp::deserialize(deserializer)? {
+        SerializedTimestamp::UnixMillis(millis) => Ok(UNIX_EPOCH + Duration::from_millis(millis)),
+        SerializedTimestamp::Rfc3339(timestamp) => humantime::parse_rfc3339_weak(&timestamp)
+            .map_err(|error| {
+                serde::de::Error::custom(format!("invalid timestamp '{}': {}", timestamp, error))
+            }),
+        SerializedTimestamp::Legacy(timestamp) => Ok(timestamp),
+    }
+}
+
+/// Nanoseconds elapsed on the monotonic clock since an origin shared by the whole
+/// process, which keep increasing even when the wall clock jumps.
+pub fn monotonic_offset_in_nanoseconds() -> u64 {
+    static ORIGIN: OnceLock<Instant> = OnceLock::new();
+
+    ORIGIN.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Timestamped {
+        timestamp: FormattedTimestamp,
+    }
+
+    #[derive(Deserialize)]
+    struct Deserialized {
+        #[serde(deserialize_with = "crate::timestamp::deserialize")]
+        timestamp: SystemTime,
+    }
+
+    #[test]
+    fn deserializes_every_format() {
+        let timestamp = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+
+        for (format, expected) in [
+            (
+                TimestampFormat::Legacy,
+                r#"{"timestamp":{"secs_since_epoch":1700000000,"nanos_since_epoch":123000000}}"#,
+            ),
+            (
+                TimestampFormat::Rfc3339,
+                r#"{"timestamp":"2023-11-14T22:13:20.123000000Z"}"#,
+            ),
+            (
+                TimestampFormat::UnixMillis,
+                r#"{"timestamp":1700000000123}"#,
+            ),
+        ] {
+            let serialized = serde_json::to_string(&Timestamped {
+                timestamp: FormattedTimestamp { timestamp, format },
+            })
+            .unwrap();
+            assert_eq!(serialized, expected);
+
+            let deserialized = serde_json::from_str::<Deserialized>(&serialized).unwrap();
+            assert_eq!(deserialized.timestamp, timestamp);
+        }
+    }
+
+    #[test]
+    fn fails_to_serialize_unrepresentable_timestamps() {
+        for (timestamp, format) in [
+            (
+                UNIX_EPOCH - Duration::from_secs(1),
+                TimestampFormat::Rfc3339,
+            ),
+            (
+                UNIX_EPOCH - Duration::from_secs(1),
+                TimestampFormat::UnixMillis,
+            ),
+            (
+                UNIX_EPOCH + Duration::from_secs(RFC3339_END_IN_SECONDS),
+                TimestampFormat::Rfc3339,
+            ),
+        ] {
+            assert!(serde_json::to_string(&FormattedTimestamp { timestamp, format }).is_err());
+        }
+    }
+}
@@ -1,12 +1,44 @@
-use crate::state::State;
+use crate::envelope::{Envelope, FormattedEnvelope};
+use crate::error::{Error, ErrorKind};
+use crate::error_info::ErrorInfo;
+use crate::labels;
+use crate::peer_credentials::PeerCredentials;
+use crate::state::{State, WireVersion};
+use crate::timestamp;
+use crate::timestamp::{FormattedTimestamp, TimestampFormat};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::time::SystemTime;
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct TrackedData {
     pub id: String,
     pub state: State,
+    /// Serialized in the format chosen for the output, see [`TrackedData::with_timestamp_format`].
+    #[serde(deserialize_with = "crate::timestamp::deserialize")]
     pub timestamp: SystemTime,
+    /// Offset of the monotonic clock when the data was generated, which orders the
+    /// data of the same process even if the wall clock jumps in between.
+    /// See [`timestamp::monotonic_offset_in_nanoseconds`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monotonic_offset_in_nanoseconds: Option<u64>,
+    /// Whether the data is a periodic repetition of the latest known state
+    /// instead of an update sent by the component itself.
+    #[serde(default)]
+    pub heartbeat: bool,
+    /// Details about the error, if the state is the outcome of one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_info: Option<ErrorInfo>,
+    /// Metadata about the origin of the data, such as the host or service which sent it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Origin of the data, set by the StateTracker when outputting it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub envelope: Option<Envelope>,
+    /// Credentials of the process which sent the data, set by the StateCollector
+    /// receiving it. Never serialized, so that it cannot be forged.
+    #[serde(skip)]
+    pub sender_credentials: Option<PeerCredentials>,
 }
 
 impl TrackedData {
@@ -15,10 +47,161 @@ impl TrackedData {
             id,
             state,
             timestamp,
+            monotonic_offset_in_nanoseconds: None,
+            heartbeat: false,
+            error_info: None,
+            labels: BTreeMap::new(),
+            envelope: None,
+            sender_credentials: None,
         }
     }
+
+    /// Generates a heartbeat repeating the data's state at the current time.
+    pub fn to_heartbeat(&self) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            monotonic_offset_in_nanoseconds: Some(timestamp::monotonic_offset_in_nanoseconds()),
+            heartbeat: true,
+            ..self.clone()
+        }
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>, Error> {
+        match serde_json::to_vec(self) {
+            Ok(serialized_data) => Ok(serialized_data),
+            Err(error) => Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("failed to serialize tracked data: {}", error),
+            )),
+        }
+    }
+
+    /// Wraps the data so that its timestamps get serialized in the `timestamp_format`,
+    /// instead of the [`TimestampFormat::Legacy`] one.
+    pub fn with_timestamp_format(
+        &self,
+        timestamp_format: TimestampFormat,
+    ) -> FormattedTrackedData<'_> {
+        FormattedTrackedData {
+            id: &self.id,
+            state: &self.state,
+            timestamp: FormattedTimestamp {
+                timestamp: self.timestamp,
+                format: timestamp_format,
+            },
+            monotonic_offset_in_nanoseconds: self.monotonic_offset_in_nanoseconds,
+            heartbeat: self.heartbeat,
+            error_info: self.error_info.as_ref(),
+            labels: &self.labels,
+            envelope: self
+                .envelope
+                .as_ref()
+                .map(|envelope| envelope.with_timestamp_format(timestamp_format)),
+        }
+    }
+
+    /// Converts the data so that it is understood by `wire_version` receivers.
+    ///
+    /// The message of a degraded state which is converted is kept in the
+    /// [`labels::DEGRADED_LABEL`] label.
+    pub fn to_wire_version(&self, wire_version: WireVersion) -> Self {
+        let mut tracked_data = Self {
+            state: self.state.to_wire_version(wire_version),
+            ..self.clone()
+        };
+
+        if let State::Degraded(message) = &self.state {
+            if tracked_data.state != self.state {
+                tracked_data
+                    .labels
+                    .insert(labels::DEGRADED_LABEL.to_string(), message.clone());
+            }
+        }
+
+        tracked_data
+    }
 }
 
+/// Serialized like the [`TrackedData`] it mirrors, field by field.
+#[derive(Serialize)]
+pub struct FormattedTrackedData<'a> {
+    id: &'a str,
+    state: &'a State,
+    timestamp: FormattedTimestamp,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    monotonic_offset_in_nanoseconds: Option<u64>,
+    heartbeat: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_info: Option<&'a ErrorInfo>,
+    #[serde(skip_serializing_if = "has_no_labels")]
+    labels: &'a BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    envelope: Option<FormattedEnvelope<'a>>,
+}
+
+fn has_no_labels(labels: &&BTreeMap<String, String>) -> bool {
+    labels.is_empty()
+}
+
+/// Generates the data of a state reached at the current time.
 pub fn generate_state_tracking_data(id: &str, state: State) -> TrackedData {
-    TrackedData::new(id.to_string(), state, SystemTime::now())
+    TrackedData {
+        monotonic_offset_in_nanoseconds: Some(timestamp::monotonic_offset_in_nanoseconds()),
+        ..TrackedData::new(id.to_string(), state, SystemTime::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::SCHEMA_VERSION;
+
+    #[test]
+    fn legacy_format_serializes_like_tracked_data() {
+        let mut tracked_data =
+            generate_state_tracking_data("ID", State::Error("error".to_string()));
+        tracked_data.error_info = Some(ErrorInfo::new("code", "message"));
+        tracked_data
+            .labels
+            .insert("host".to_string(), "localhost".to_string());
+        tracked_data.envelope = Some(Envelope {
+            schema_version: SCHEMA_VERSION,
+            instance_id: uuid::Uuid::new_v4(),
+            booted_at: SystemTime::now(),
+            sequence: 1,
+        });
+
+        for tracked_data in [
+            TrackedData::new("ID".to_string(), State::Valid, SystemTime::now()),
+            tracked_data,
+        ] {
+            assert_eq!(
+                serde_json::to_vec(&tracked_data.with_timestamp_format(TimestampFormat::Legacy))
+                    .unwrap(),
+                tracked_data.to_json().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_degraded_message_in_label() {
+        const MESSAGE: &str = "MESSAGE";
+
+        let tracked_data = TrackedData::new(
+            "ID".to_string(),
+            State::Degraded(MESSAGE.to_string()),
+            SystemTime::now(),
+        );
+
+        let v1_tracked_data = tracked_data.to_wire_version(WireVersion::V1);
+        assert_eq!(v1_tracked_data.state, State::Valid);
+        assert_eq!(
+            v1_tracked_data.labels.get(labels::DEGRADED_LABEL).unwrap(),
+            MESSAGE
+        );
+
+        let v2_tracked_data = tracked_data.to_wire_version(WireVersion::V2);
+        assert_eq!(v2_tracked_data.state, tracked_data.state);
+        assert!(v2_tracked_data.labels.is_empty());
+    }
 }
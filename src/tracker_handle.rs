@@ -0,0 +1,60 @@
+use crate::error::{Error, ErrorKind};
+use crate::retry::RetryStats;
+
+use std::sync::Arc;
+use tokio::sync::oneshot::{Receiver, Sender};
+use tokio::task::JoinHandle;
+
+/// Handle to a running StateTracker.
+///
+/// Dropping it leaves the tracker running until every client has been dropped.
+pub struct TrackerHandle {
+    shutdown_sender: Sender<()>,
+    join_handle: JoinHandle<()>,
+    retry_stats: Arc<RetryStats>,
+}
+
+impl TrackerHandle {
+    pub(crate) fn new(
+        shutdown_sender: Sender<()>,
+        join_handle: JoinHandle<()>,
+        retry_stats: Arc<RetryStats>,
+    ) -> TrackerHandle {
+        TrackerHandle {
+            shutdown_sender,
+            join_handle,
+            retry_stats,
+        }
+    }
+
+    pub fn retry_stats(&self) -> &RetryStats {
+        &self.retry_stats
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.join_handle.is_finished()
+    }
+
+    /// Stops the tracker after outputting the data which is already queued,
+    /// waiting until it has finished.
+    pub async fn shutdown(self) -> Result<(), Error> {
+        // The tracker may have already stopped by itself.
+        let _ = self.shutdown_sender.send(());
+
+        match self.join_handle.await {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("state tracker failed while stopping: {}", error),
+            )),
+        }
+    }
+}
+
+/// Completes once a shutdown is requested through the handle owning the paired sender.
+pub(crate) async fn shutdown_requested(shutdown_receiver: Receiver<()>) {
+    // A dropped handle must not stop the tracker.
+    if shutdown_receiver.await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
@@ -0,0 +1,172 @@
+use crate::encoding::PayloadFormat;
+use crate::error::{Error, ErrorKind};
+use crate::output_sink::OutputSink;
+use crate::oversize::OversizeConfig;
+use crate::tracked_data::TrackedData;
+
+use async_trait::async_trait;
+use std::io;
+use tokio::net::UdpSocket;
+
+/// Outputs every TrackedData as a datagram, JSON by default, sent over UDP.
+///
+/// The receiver's address gets resolved on the first emission, and again after
+/// sending to it failed, e.g. because the receiver moved to another address.
+pub struct UdpSink {
+    address: String,
+    socket: Option<UdpSocket>,
+    payload_format: PayloadFormat,
+    oversize_config: OversizeConfig,
+}
+
+impl UdpSink {
+    /// # Arguments
+    /// * `address` - Address of the receiver, such as `collector:7070`.
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            socket: None,
+            payload_format: PayloadFormat::default(),
+            oversize_config: OversizeConfig::default(),
+        }
+    }
+
+    /// Sets how the outputs are serialized, which is as uncompressed JSON by default.
+    pub fn with_payload_format(mut self, payload_format: PayloadFormat) -> Self {
+        self.payload_format = payload_format;
+        self
+    }
+
+    /// Sets how the outputs which do not fit into a datagram are handled, which is
+    /// by truncating them by default.
+    pub fn with_oversize_config(mut self, oversize_config: OversizeConfig) -> Self {
+        self.oversize_config = oversize_config;
+        self
+    }
+
+    async fn connect(&self) -> io::Result<UdpSocket> {
+        let address = match tokio::net::lookup_host(&self.address).await?.next() {
+            Some(address) => address,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "address did not resolve",
+                ))
+            }
+        };
+
+        let local_address = if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+
+        let socket = UdpSocket::bind(local_address).await?;
+        socket.connect(address).await?;
+
+        Ok(socket)
+    }
+}
+
+#[async_trait]
+impl OutputSink for UdpSink {
+    async fn emit(&mut self, tracked_data: &TrackedData) -> Result<(), Error> {
+        let datagrams = self
+            .oversize_config
+            .fit(tracked_data, &self.payload_format)?;
+
+        let socket = match self.socket.take() {
+            Some(socket) => socket,
+            None => match self.connect().await {
+                Ok(socket) => socket,
+                Err(error) => {
+                    return Err(Error::new(
+                        ErrorKind::InternalFailure,
+                        format!("failed to connect to '{}': {}", self.address, error),
+                    ))
+                }
+            },
+        };
+        let socket = self.socket.insert(socket);
+
+        for datagram in datagrams {
+            if let Err(error) = socket.send(&datagram).await {
+                self.socket = None;
+
+                return Err(Error::new(
+                    ErrorKind::InternalFailure,
+                    format!("failed to send to '{}': {}", self.address, error),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+    use std::time::{Duration, SystemTime};
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn outputs_datagrams() {
+        const TEST_ID: &str = "test_id";
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut sink = UdpSink::new(&receiver.local_addr().unwrap().to_string());
+
+        sink.emit(&TrackedData::new(
+            TEST_ID.to_string(),
+            State::Valid,
+            SystemTime::now(),
+        ))
+        .await
+        .unwrap();
+
+        let mut buffer = [0; 1024];
+        let length = timeout(Duration::from_secs(3), receiver.recv(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+        let tracked_data = serde_json::from_slice::<TrackedData>(&buffer[..length]).unwrap();
+
+        assert_eq!(tracked_data.id, TEST_ID);
+        assert_eq!(tracked_data.state, State::Valid);
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_failing_to_send() {
+        let tracked_data = TrackedData::new("test_id".to_string(), State::Valid, SystemTime::now());
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let receiver_address = receiver.local_addr().unwrap();
+        let mut sink = UdpSink::new(&receiver_address.to_string());
+
+        let mut buffer = [0; 1024];
+        sink.emit(&tracked_data).await.unwrap();
+        let (_, first_sender_address) = receiver.recv_from(&mut buffer).await.unwrap();
+
+        // Sending fails once the port unreachable reply of the missing receiver arrives.
+        drop(receiver);
+        timeout(Duration::from_secs(3), async {
+            while sink.emit(&tracked_data).await.is_ok() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let receiver = UdpSocket::bind(receiver_address).await.unwrap();
+        sink.emit(&tracked_data).await.unwrap();
+        let (_, second_sender_address) =
+            timeout(Duration::from_secs(3), receiver.recv_from(&mut buffer))
+                .await
+                .unwrap()
+                .unwrap();
+
+        assert_ne!(first_sender_address, second_sender_address);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::encoding::PayloadFormat;
+use crate::error;
+use crate::error::{Error, ErrorKind};
+use crate::output_sink::OutputSink;
+use crate::oversize::OversizeConfig;
+use crate::tracked_data::TrackedData;
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::net::UnixDatagram;
+
+/// Outputs every TrackedData as a datagram, JSON by default, sent to an UnixDatagram socket.
+pub struct UnixDatagramSink {
+    output_sender: Arc<UnixDatagram>,
+    output_receiver_path: String,
+    payload_format: PayloadFormat,
+    oversize_config: OversizeConfig,
+}
+
+impl UnixDatagramSink {
+    /// Tries to create an instance of UnixDatagramSink.
+    ///
+    /// # Arguments
+    /// * `output_sender_path` - Path to the UnixDatagram socket that will send the outputs.
+    /// * `output_receiver_path` - Path to the UnixDatagram socket that will receive the outputs.
+    pub fn try_new(output_sender_path: &str, output_receiver_path: &str) -> Result<Self, Error> {
+        match UnixDatagram::bind(output_sender_path) {
+            Ok(output_sender) => Ok(Self::new(Arc::new(output_sender), output_receiver_path)),
+            Err(error) => Err(error::bind_error(output_sender_path, error)),
+        }
+    }
+
+    /// Creates an instance of UnixDatagramSink sending through an already bound socket,
+    /// which may be shared with other sinks.
+    pub fn new(output_sender: Arc<UnixDatagram>, output_receiver_path: &str) -> Self {
+        Self {
+            output_sender,
+            output_receiver_path: output_receiver_path.to_string(),
+            payload_format: PayloadFormat::default(),
+            oversize_config: OversizeConfig::default(),
+        }
+    }
+
+    /// Sets how the outputs are serialized, which is as uncompressed JSON by default.
+    pub fn with_payload_format(mut self, payload_format: PayloadFormat) -> Self {
+        self.payload_format = payload_format;
+        self
+    }
+
+    /// Sets how the outputs which do not fit into a datagram are handled, which is
+    /// by truncating them by default.
+    pub fn with_oversize_config(mut self, oversize_config: OversizeConfig) -> Self {
+        self.oversize_config = oversize_config;
+        self
+    }
+}
+
+#[async_trait]
+impl OutputSink for UnixDatagramSink {
+    async fn emit(&mut self, tracked_data: &TrackedData) -> Result<(), Error> {
+        let datagrams = self
+            .oversize_config
+            .fit(tracked_data, &self.payload_format)?;
+
+        for datagram in datagrams {
+            if let Err(error) = self
+                .output_sender
+                .send_to(datagram.as_slice(), &self.output_receiver_path)
+                .await
+            {
+                return Err(Error::new(
+                    ErrorKind::InternalFailure,
+                    format!("failed to write to output socket: {}", error),
+                ));
+            }
+        }
+
+        log::info!("sent data to output socket");
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use crate::encoding::PayloadFormat;
+use crate::error::Error;
+use crate::output_sink::OutputSink;
+use crate::reconnecting_stream::ReconnectingStream;
+use crate::tracked_data::TrackedData;
+
+use async_trait::async_trait;
+use futures::FutureExt;
+use tokio::net::UnixStream;
+
+/// Outputs every TrackedData as a frame, JSON by default, see [`crate::framing`], written to an
+/// UnixStream socket.
+///
+/// The socket gets connected on the first emission and reconnected whenever the
+/// receiver restarts, waiting longer after every failed attempt.
+pub struct UnixStreamSink {
+    stream: ReconnectingStream<UnixStream>,
+    payload_format: PayloadFormat,
+}
+
+impl UnixStreamSink {
+    /// # Arguments
+    /// * `receiver_path` - Path to the UnixStream socket that will receive the outputs.
+    pub fn new(receiver_path: &str) -> Self {
+        let path = receiver_path.to_string();
+
+        Self {
+            stream: ReconnectingStream::new(
+                receiver_path.to_string(),
+                Box::new(move || UnixStream::connect(path.clone()).boxed()),
+            ),
+            payload_format: PayloadFormat::default(),
+        }
+    }
+
+    /// Sets how the outputs are serialized, which is as uncompressed JSON by default.
+    pub fn with_payload_format(mut self, payload_format: PayloadFormat) -> Self {
+        self.payload_format = payload_format;
+        self
+    }
+}
+
+#[async_trait]
+impl OutputSink for UnixStreamSink {
+    async fn emit(&mut self, tracked_data: &TrackedData) -> Result<(), Error> {
+        let serialized_data = self.payload_format.serialize(tracked_data)?;
+
+        self.stream.write_frame(&serialized_data).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        self.stream.flush().await
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        self.stream.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing;
+    use crate::state::State;
+    use std::time::SystemTime;
+    use tokio::net::UnixListener;
+
+    async fn receive(listener: &UnixListener) -> (UnixStream, TrackedData) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let frame = framing::read_frame(&mut stream).await.unwrap().unwrap();
+
+        (stream, serde_json::from_slice(&frame).unwrap())
+    }
+
+    #[tokio::test]
+    async fn reconnects_when_receiver_restarts() {
+        const RECEIVER_PATH: &str = "/tmp/cooplan_unix_stream_sink_test_receiver.sock";
+        const TEST_ID: &str = "test_id";
+
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+        let listener = UnixListener::bind(RECEIVER_PATH).unwrap();
+
+        let mut sink = UnixStreamSink::new(RECEIVER_PATH);
+        let tracked_data = TrackedData::new(TEST_ID.to_string(), State::Valid, SystemTime::now());
+
+        sink.emit(&tracked_data).await.unwrap();
+        let (stream, received) = receive(&listener).await;
+        assert_eq!(received.id, TEST_ID);
+
+        drop(stream);
+        drop(listener);
+        tokio::fs::remove_file(RECEIVER_PATH).await.unwrap();
+        let listener = UnixListener::bind(RECEIVER_PATH).unwrap();
+
+        sink.emit(&tracked_data).await.unwrap();
+        let (_, received) = receive(&listener).await;
+        assert_eq!(received.id, TEST_ID);
+    }
+
+    #[tokio::test]
+    async fn fails_while_receiver_is_missing() {
+        const RECEIVER_PATH: &str = "/tmp/cooplan_unix_stream_sink_test_missing_receiver.sock";
+
+        let _ = tokio::fs::remove_file(RECEIVER_PATH).await;
+
+        let mut sink = UnixStreamSink::new(RECEIVER_PATH);
+        let tracked_data = TrackedData::new("test_id".to_string(), State::Valid, SystemTime::now());
+
+        assert!(sink.emit(&tracked_data).await.is_err());
+        // Waiting for the backoff to elapse, without trying to connect.
+        assert!(sink.emit(&tracked_data).await.is_err());
+    }
+}